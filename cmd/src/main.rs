@@ -12,11 +12,12 @@ use gumdrop::Options;
 use mpc_api::RpcApi;
 use mpc_p2p::{NetworkWorker, NodeKeyConfig, Params, RoomArgs, Secret};
 use mpc_rpc::server::JsonRPCServer;
-use mpc_runtime::{PersistentCacher, RuntimeDaemon};
+use mpc_runtime::{PersistentCacher, RuntimeConfig, RuntimeDaemon};
 use mpc_tss::{generate_config, Config, TssFactory};
 use sha3::Digest;
 use std::error::Error;
 use std::path::Path;
+use std::time::Duration;
 use std::{iter, process};
 
 #[tokio::main]
@@ -75,6 +76,15 @@ async fn deploy(args: DeployArgs) -> Result<(), anyhow::Error> {
             rooms: vec![room_cfg],
             mdns: args.mdns,
             kademlia: args.kademlia,
+            kademlia_bootstrap_max_retries: 5,
+            kademlia_bootstrap_base_delay: Duration::from_secs(1),
+            kademlia_bootstrap_max_delay: Duration::from_secs(60),
+            boot_peers_timeout: Duration::from_secs(30),
+            kademlia_store: None,
+            kad_protocol_name: None,
+            dial_failure_threshold: 5,
+            dial_failure_window: Duration::from_secs(60),
+            dial_failure_cooldown: Duration::from_secs(300),
         };
 
         NetworkWorker::new(node_key, cfg)?
@@ -91,7 +101,9 @@ async fn deploy(args: DeployArgs) -> Result<(), anyhow::Error> {
         iter::once((room_id, room_rx)),
         TssFactory::new(format!("data/{}/key.share", local_peer_id.to_base58())),
         PersistentCacher::new(base_path.join("peerset"), local_peer_id.clone()),
-    );
+        RuntimeConfig::default(),
+    )
+    .map_err(|e| anyhow!("failed starting runtime: {e}"))?;
 
     let rt_task = task::spawn(async {
         rt_worker.run().await;
@@ -1,4 +1,6 @@
-use crate::discovery::{DiscoveryBehaviour, DiscoveryOut};
+use crate::discovery::{DiscoveryBehaviour, DiscoveryError, DiscoveryOut};
+#[cfg(feature = "metrics")]
+use crate::metrics::DiscoveryMetrics;
 use crate::{broadcast, MessageContext, Params, RoomId};
 use futures::channel::mpsc;
 use libp2p::identify::{Identify, IdentifyConfig, IdentifyEvent};
@@ -8,10 +10,12 @@ use libp2p::ping::{Ping, PingEvent, PingFailure, PingSuccess};
 use libp2p::swarm::NetworkBehaviourEventProcess;
 use libp2p::swarm::{NetworkBehaviour, NetworkBehaviourAction, PollParameters};
 use libp2p::NetworkBehaviour;
-use libp2p::PeerId;
+use libp2p::{Multiaddr, PeerId};
 use log::{debug, trace};
 use std::borrow::Cow;
 use std::collections::VecDeque;
+#[cfg(feature = "metrics")]
+use std::sync::Arc;
 use std::task::{Context, Poll};
 
 const MPC_PROTOCOL_ID: &str = "/mpc/0.1.0";
@@ -36,6 +40,12 @@ pub(crate) enum BehaviourOut {
         /// Protocol name of the request.
         protocol: Cow<'static, str>,
     },
+    /// Connection with the peer has been established.
+    PeerConnected(PeerId),
+    /// Connection with the peer has been closed.
+    PeerDisconnected(PeerId),
+    /// None of the boot peers could be reached.
+    NoBootPeersReachable,
 }
 
 impl Behaviour {
@@ -56,6 +66,12 @@ impl Behaviour {
         })
     }
 
+    /// Returns metrics of the discovery behaviour.
+    #[cfg(feature = "metrics")]
+    pub fn discovery_metrics(&self) -> Arc<DiscoveryMetrics> {
+        self.discovery.metrics()
+    }
+
     /// Initiates direct sending of a message.
     pub fn send_message(
         &mut self,
@@ -99,10 +115,15 @@ impl Behaviour {
     }
 
     /// Bootstrap Kademlia network.
-    pub fn bootstrap(&mut self) -> Result<QueryId, String> {
+    pub fn bootstrap(&mut self) -> Result<QueryId, DiscoveryError> {
         self.discovery.bootstrap()
     }
 
+    /// Known addresses of the peer.
+    pub fn known_addresses(&self, peer_id: &PeerId) -> Vec<Multiaddr> {
+        self.discovery.known_addresses(peer_id)
+    }
+
     /// Known peers.
     pub fn peers(&self, _room_id: RoomId) -> impl Iterator<Item = PeerId> {
         self.discovery.peers().clone().into_iter()
@@ -155,8 +176,16 @@ impl NetworkBehaviourEventProcess<broadcast::BroadcastOut> for Behaviour {
 impl NetworkBehaviourEventProcess<DiscoveryOut> for Behaviour {
     fn inject_event(&mut self, event: DiscoveryOut) {
         match event {
-            DiscoveryOut::Connected(..) => {}
-            DiscoveryOut::Disconnected(..) => {}
+            DiscoveryOut::Connected(peer_id) => {
+                self.events.push_back(BehaviourOut::PeerConnected(peer_id));
+            }
+            DiscoveryOut::Disconnected(peer_id) => {
+                self.events
+                    .push_back(BehaviourOut::PeerDisconnected(peer_id));
+            }
+            DiscoveryOut::NoBootPeersReachable => {
+                self.events.push_back(BehaviourOut::NoBootPeersReachable);
+            }
         }
     }
 }
@@ -1,4 +1,4 @@
-use crate::{broadcast, RoomId};
+use crate::{broadcast, RoomId, StoreFactory};
 use anyhow::anyhow;
 use futures::channel::mpsc;
 use libp2p::identity::{ed25519, Keypair};
@@ -8,6 +8,7 @@ use std::error::Error;
 use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::time::Duration;
 use std::{fmt, fs, io};
 use zeroize::Zeroize;
 
@@ -19,6 +20,27 @@ pub struct Params {
     pub mdns: bool,
     /// Kademlia discovery enabled.
     pub kademlia: bool,
+    /// Maximum number of times Kademlia bootstrap is retried before giving up.
+    pub kademlia_bootstrap_max_retries: u32,
+    /// Delay before the first Kademlia bootstrap retry, doubled after each attempt.
+    pub kademlia_bootstrap_base_delay: Duration,
+    /// Upper bound of the delay between Kademlia bootstrap retries.
+    pub kademlia_bootstrap_max_delay: Duration,
+    /// Time to wait for any of the boot peers to connect before reporting them unreachable.
+    pub boot_peers_timeout: Duration,
+    /// Builds the Kademlia record store, [`MemoryStore`](libp2p::kad::record::store::MemoryStore)
+    /// is used if not set.
+    pub kademlia_store: Option<StoreFactory>,
+    /// Kademlia protocol name, overrides the default `/ipfs/kad/1.0.0` to keep DHT of the
+    /// deployment isolated from other networks.
+    pub kad_protocol_name: Option<String>,
+    /// Number of consecutive dial failures after which a peer is blacklisted, `0` disables it.
+    /// User-defined boot peers are never blacklisted.
+    pub dial_failure_threshold: u32,
+    /// Time window in which the dial failures must occur to count as consecutive.
+    pub dial_failure_window: Duration,
+    /// Time for which the blacklisted peer isn't dialed.
+    pub dial_failure_cooldown: Duration,
     /// Rooms
     pub rooms: Vec<RoomArgs>,
 }
@@ -1,6 +1,9 @@
-use crate::Params;
+#[cfg(feature = "metrics")]
+use crate::metrics::DiscoveryMetrics;
+use crate::{BoxedStore, DiscoveryStore, Params};
 use async_std::task;
 use futures::prelude::*;
+use futures_timer::Delay;
 
 use libp2p::swarm::DialError;
 use libp2p::{
@@ -8,7 +11,10 @@ use libp2p::{
         connection::{ConnectionId, ListenerId},
         ConnectedPoint, Multiaddr, PeerId, PublicKey,
     },
-    kad::{handler::KademliaHandlerProto, Kademlia, KademliaConfig, KademliaEvent, QueryId},
+    kad::{
+        handler::KademliaHandlerProto, Kademlia, KademliaConfig, KademliaEvent, NoKnownPeers,
+        QueryId, QueryResult,
+    },
     mdns::MdnsEvent,
     swarm::{
         toggle::{Toggle, ToggleIntoProtoHandler},
@@ -19,21 +25,45 @@ use libp2p::{
 use libp2p::{kad::record::store::MemoryStore, mdns::Mdns};
 use log::{debug, error, info, trace, warn};
 
+use std::collections::hash_map::Entry;
 use std::collections::HashMap;
+#[cfg(feature = "metrics")]
+use std::sync::Arc;
 use std::{
     collections::{HashSet, VecDeque},
     io,
     task::{Context, Poll},
+    time::{Duration, Instant},
 };
 
 /// Event generated by the `DiscoveryBehaviour`.
 #[derive(Debug)]
 pub enum DiscoveryOut {
     /// Event that notifies that we connected to the node with the given peer id.
+    ///
+    /// Emitted for every peer rather than only once a boot peer is reached, since the runtime
+    /// relies on it to learn that the session parties are connected, and those needn't be boot
+    /// peers. Reaching a boot peer only stops the Kademlia bootstrap retries.
     Connected(PeerId),
 
     /// Event that notifies that we disconnected with the node with the given peer id.
     Disconnected(PeerId),
+
+    /// Event that notifies that none of the user-defined boot peers has connected within
+    /// [`Params::boot_peers_timeout`], since the start or since dialing all of them has failed
+    /// again. Emitted once, until any of the boot peers is connected again.
+    NoBootPeersReachable,
+}
+
+/// Error returned by [`DiscoveryBehaviour::bootstrap`].
+#[derive(Debug, thiserror::Error)]
+pub enum DiscoveryError {
+    /// Kademlia discovery isn't enabled in the [`Params`].
+    #[error("Kademlia is not activated")]
+    KademliaDisabled,
+    /// Kademlia has no known peers to bootstrap against.
+    #[error("{0}")]
+    BootstrapFailed(#[from] NoKnownPeers),
 }
 
 /// Implementation of `NetworkBehaviour` that discovers the nodes on the network.
@@ -42,7 +72,7 @@ pub struct DiscoveryBehaviour {
     /// reserved nodes.
     user_defined: Vec<(PeerId, Multiaddr)>,
     /// Kademlia discovery.
-    kademlia: Toggle<Kademlia<MemoryStore>>,
+    kademlia: Toggle<Kademlia<BoxedStore>>,
     /// Discovers nodes on the local network.
     mdns: Toggle<Mdns>,
     /// Events to return in priority when polled.
@@ -53,6 +83,53 @@ pub struct DiscoveryBehaviour {
     peers: HashSet<PeerId>,
     /// Keeps hash map of peers and their multiaddresses
     peer_addresses: HashMap<PeerId, Vec<Multiaddr>>,
+    /// Addresses of the peers discovered through Kademlia and mDNS.
+    discovered_addresses: HashMap<PeerId, Vec<Multiaddr>>,
+    /// State of the Kademlia bootstrap retries.
+    bootstrap: BootstrapState,
+    /// User-defined peers that failed to be dialed since the last time any of them connected.
+    unreachable_boot_peers: HashSet<PeerId>,
+    /// Whether `NoBootPeersReachable` has already been emitted.
+    no_boot_peers_reported: bool,
+    /// Fires when the boot peers are considered unreachable. `None` once any of them connects.
+    boot_peers_deadline: Option<Delay>,
+    /// See [`Params::boot_peers_timeout`].
+    boot_peers_timeout: Duration,
+    /// Consecutive dial failures of the peers that aren't user-defined.
+    dial_failures: HashMap<PeerId, DialFailures>,
+    /// Peers withheld from dialing, along with the time their cooldown expires.
+    blacklist: HashMap<PeerId, Instant>,
+    /// See [`Params::dial_failure_threshold`].
+    dial_failure_threshold: u32,
+    /// See [`Params::dial_failure_window`].
+    dial_failure_window: Duration,
+    /// See [`Params::dial_failure_cooldown`].
+    dial_failure_cooldown: Duration,
+    /// Connection and discovery metrics, shared with the network service.
+    #[cfg(feature = "metrics")]
+    metrics: Arc<DiscoveryMetrics>,
+}
+
+/// Dial failures of a peer within the current window.
+struct DialFailures {
+    /// Number of dial failures since the window started.
+    count: u32,
+    /// Time of the first failure in the window.
+    since: Instant,
+}
+
+/// Keeps track of Kademlia bootstrap attempts until one of the boot peers is reached.
+struct BootstrapState {
+    /// Fires when the next bootstrap check is due. `None` when no more retries are planned.
+    next_attempt: Option<Delay>,
+    /// Delay to wait before the next check, doubled after each retry.
+    delay: Duration,
+    /// Delay isn't doubled beyond this.
+    max_delay: Duration,
+    /// Number of retries left before giving up.
+    retries_left: u32,
+    /// Whether the last bootstrap attempt has failed.
+    failed: bool,
 }
 
 impl DiscoveryBehaviour {
@@ -68,10 +145,26 @@ impl DiscoveryBehaviour {
             .map(|mwp| (mwp.peer_id, mwp.multiaddr))
             .collect();
 
+        let mut bootstrap = BootstrapState {
+            next_attempt: None,
+            delay: params
+                .kademlia_bootstrap_base_delay
+                .min(params.kademlia_bootstrap_max_delay),
+            max_delay: params.kademlia_bootstrap_max_delay,
+            retries_left: params.kademlia_bootstrap_max_retries,
+            failed: false,
+        };
+
         let kademlia_opt = {
             // Kademlia config
-            let store = MemoryStore::new(local_peer_id.to_owned());
-            let kad_config = KademliaConfig::default();
+            let store = BoxedStore::new(match params.kademlia_store.as_ref() {
+                Some(make_store) => make_store(local_peer_id),
+                None => Box::new(MemoryStore::new(local_peer_id)) as Box<dyn DiscoveryStore>,
+            });
+            let mut kad_config = KademliaConfig::default();
+            if let Some(protocol_name) = params.kad_protocol_name.as_ref() {
+                kad_config.set_protocol_name(protocol_name.as_bytes().to_vec());
+            }
 
             if params.kademlia {
                 let mut kademlia = Kademlia::with_config(local_peer_id, store, kad_config);
@@ -82,6 +175,10 @@ impl DiscoveryBehaviour {
                 info!("kademlia peers: {:?}", peers);
                 if let Err(e) = kademlia.bootstrap() {
                     warn!("Kademlia bootstrap failed: {}", e);
+                    bootstrap.failed = true;
+                }
+                if bootstrap.retries_left > 0 {
+                    bootstrap.next_attempt = Some(Delay::new(bootstrap.delay));
                 }
                 Some(kademlia)
             } else {
@@ -107,9 +204,29 @@ impl DiscoveryBehaviour {
             mdns: mdns_opt.into(),
             peers,
             peer_addresses,
+            discovered_addresses: HashMap::new(),
+            bootstrap,
+            boot_peers_deadline: (!user_defined.is_empty())
+                .then(|| Delay::new(params.boot_peers_timeout)),
+            boot_peers_timeout: params.boot_peers_timeout,
+            unreachable_boot_peers: HashSet::new(),
+            no_boot_peers_reported: false,
+            dial_failures: HashMap::new(),
+            blacklist: HashMap::new(),
+            dial_failure_threshold: params.dial_failure_threshold,
+            dial_failure_window: params.dial_failure_window,
+            dial_failure_cooldown: params.dial_failure_cooldown,
+            #[cfg(feature = "metrics")]
+            metrics: Default::default(),
         }
     }
 
+    /// Returns metrics updated by this behaviour.
+    #[cfg(feature = "metrics")]
+    pub fn metrics(&self) -> Arc<DiscoveryMetrics> {
+        self.metrics.clone()
+    }
+
     /// Returns reference to peer set.
     pub fn peers(&self) -> &HashSet<PeerId> {
         &self.peers
@@ -120,14 +237,168 @@ impl DiscoveryBehaviour {
         &self.peer_addresses
     }
 
+    /// Returns the addresses currently known for `peer_id`, whether user-defined, recorded on
+    /// connection or discovered, without any duplicates.
+    pub fn known_addresses(&self, peer_id: &PeerId) -> Vec<Multiaddr> {
+        let user_defined =
+            self.user_defined
+                .iter()
+                .filter_map(|(p, a)| if p == peer_id { Some(a) } else { None });
+        let connected = self.peer_addresses.get(peer_id).into_iter().flatten();
+        let discovered = self.discovered_addresses.get(peer_id).into_iter().flatten();
+
+        let mut list = Vec::new();
+        for addr in user_defined.chain(connected).chain(discovered) {
+            if !list.contains(addr) {
+                list.push(addr.clone());
+            }
+        }
+
+        list
+    }
+
+    /// Remembers the discovered `addr` of `peer_id`, counting the peer as discovered unless its
+    /// other addresses are already known, e.g. when mDNS discovers it again.
+    fn add_discovered_address(&mut self, peer_id: PeerId, addr: Multiaddr) {
+        let addresses = match self.discovered_addresses.entry(peer_id) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                #[cfg(feature = "metrics")]
+                self.metrics.peer_discovered();
+                entry.insert(vec![])
+            }
+        };
+        if !addresses.contains(&addr) {
+            addresses.push(addr);
+        }
+    }
+
+    /// Forgets the discovered `addr` of `peer_id`, e.g. once its mDNS record has expired.
+    fn remove_discovered_address(&mut self, peer_id: &PeerId, addr: &Multiaddr) {
+        if let Some(addresses) = self.discovered_addresses.get_mut(peer_id) {
+            addresses.retain(|a| a != addr);
+            if addresses.is_empty() {
+                self.discovered_addresses.remove(peer_id);
+            }
+        }
+    }
+
     /// Bootstrap Kademlia network
-    pub fn bootstrap(&mut self) -> Result<QueryId, String> {
+    pub fn bootstrap(&mut self) -> Result<QueryId, DiscoveryError> {
         if let Some(active_kad) = self.kademlia.as_mut() {
-            active_kad.bootstrap().map_err(|e| e.to_string())
+            Ok(active_kad.bootstrap()?)
         } else {
-            Err("Kademlia is not activated".to_string())
+            Err(DiscoveryError::KademliaDisabled)
+        }
+    }
+
+    /// Counts the failed dial of `peer_id`, blacklisting it once the failures reach the threshold
+    /// within the window. User-defined and already blacklisted peers are exempt.
+    fn record_dial_failure(&mut self, peer_id: PeerId, now: Instant) {
+        if self.dial_failure_threshold == 0
+            || self.blacklist.contains_key(&peer_id)
+            || self.user_defined.iter().any(|(p, _)| *p == peer_id)
+        {
+            return;
+        }
+
+        let failures = self.dial_failures.entry(peer_id).or_insert(DialFailures {
+            count: 0,
+            since: now,
+        });
+        if now.duration_since(failures.since) > self.dial_failure_window {
+            failures.count = 0;
+            failures.since = now;
+        }
+        failures.count += 1;
+
+        if failures.count >= self.dial_failure_threshold {
+            debug!(
+                "Dialing {} failed {} times, blacklisting it for {:?}",
+                peer_id, failures.count, self.dial_failure_cooldown
+            );
+            self.dial_failures.remove(&peer_id);
+            self.discovered_addresses.remove(&peer_id);
+            self.blacklist
+                .insert(peer_id, now + self.dial_failure_cooldown);
         }
     }
+
+    /// Whether `peer_id` is blacklisted at `now`, lifting the expired blacklisting.
+    fn is_blacklisted(&mut self, peer_id: &PeerId, now: Instant) -> bool {
+        match self.blacklist.get(peer_id) {
+            Some(until) if now < *until => true,
+            Some(_) => {
+                self.blacklist.remove(peer_id);
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// Emits `NoBootPeersReachable`, unless it was already emitted since a boot peer connected.
+    fn report_no_boot_peers(&mut self) {
+        if !self.no_boot_peers_reported {
+            warn!("None of the boot peers are reachable, check the network configuration");
+            self.no_boot_peers_reported = true;
+            self.pending_events
+                .push_back(DiscoveryOut::NoBootPeersReachable);
+        }
+    }
+
+    /// Reports the boot peers unreachable if none of them has connected before the deadline.
+    fn poll_boot_peers_deadline(&mut self, cx: &mut Context) {
+        if let Some(deadline) = self.boot_peers_deadline.as_mut() {
+            if deadline.poll_unpin(cx).is_ready() {
+                self.boot_peers_deadline = None;
+                self.report_no_boot_peers();
+            }
+        }
+    }
+
+    /// Bootstraps Kademlia again if the previous attempt failed or didn't populate the routing
+    /// table, and schedules the next check with exponential backoff.
+    fn retry_bootstrap(&mut self) {
+        self.bootstrap.next_attempt = None;
+
+        let kad = match self.kademlia.as_mut() {
+            Some(kad) => kad,
+            None => return,
+        };
+
+        let routing_table_empty = kad.kbuckets().all(|bucket| bucket.num_entries() == 0);
+        if !self.bootstrap.failed && !routing_table_empty {
+            debug!("Kademlia bootstrap succeeded, no more retries needed");
+            return;
+        }
+
+        if self.bootstrap.retries_left == 0 {
+            warn!("Kademlia bootstrap failed, no more retries left");
+            return;
+        }
+        self.bootstrap.retries_left -= 1;
+
+        // Failed dials evict boot peers from the routing table, so they are re-added each time.
+        for (peer_id, addr) in self.user_defined.iter() {
+            kad.add_address(peer_id, addr.clone());
+        }
+
+        self.bootstrap.failed = match kad.bootstrap() {
+            Ok(_) => false,
+            Err(e) => {
+                warn!("Kademlia bootstrap retry failed: {}", e);
+                true
+            }
+        };
+
+        self.bootstrap.delay = next_bootstrap_delay(self.bootstrap.delay, self.bootstrap.max_delay);
+        self.bootstrap.next_attempt = Some(Delay::new(self.bootstrap.delay));
+    }
+}
+
+/// Doubles the bootstrap retry `delay`, up to the `max_delay`.
+fn next_bootstrap_delay(delay: Duration, max_delay: Duration) -> Duration {
+    delay.saturating_mul(2).min(max_delay)
 }
 
 impl NetworkBehaviour for DiscoveryBehaviour {
@@ -139,6 +410,14 @@ impl NetworkBehaviour for DiscoveryBehaviour {
     }
 
     fn addresses_of_peer(&mut self, peer_id: &PeerId) -> Vec<Multiaddr> {
+        if self.is_blacklisted(peer_id, Instant::now()) {
+            trace!(
+                "Peer {:?} is blacklisted, withholding its addresses",
+                peer_id
+            );
+            return vec![];
+        }
+
         let mut list = self
             .user_defined
             .iter()
@@ -162,11 +441,38 @@ impl NetworkBehaviour for DiscoveryBehaviour {
     }
 
     fn inject_connected(&mut self, peer_id: &PeerId) {
+        // Peer reachable again, e.g. connected inbound, no longer needs its addresses withheld.
+        self.dial_failures.remove(peer_id);
+        if self.blacklist.remove(peer_id).is_some() {
+            debug!(
+                "Connected to blacklisted peer {}, lifting its blacklisting",
+                peer_id
+            );
+        }
+
+        if self.user_defined.iter().any(|(p, _)| p == peer_id) {
+            self.unreachable_boot_peers.clear();
+            self.no_boot_peers_reported = false;
+            self.boot_peers_deadline = None;
+        }
+
+        if self.bootstrap.next_attempt.is_some()
+            && self.user_defined.iter().any(|(p, _)| p == peer_id)
+        {
+            debug!(
+                "Reached boot peer {}, stopping Kademlia bootstrap retries",
+                peer_id
+            );
+            self.bootstrap.next_attempt = None;
+        }
+
         let multiaddr = self.addresses_of_peer(peer_id);
         self.peer_addresses.insert(*peer_id, multiaddr);
         self.peers.insert(*peer_id);
         self.pending_events
             .push_back(DiscoveryOut::Connected(*peer_id));
+        #[cfg(feature = "metrics")]
+        self.metrics.peer_connected();
 
         self.kademlia.inject_connected(peer_id)
     }
@@ -174,6 +480,8 @@ impl NetworkBehaviour for DiscoveryBehaviour {
     fn inject_disconnected(&mut self, peer_id: &PeerId) {
         self.pending_events
             .push_back(DiscoveryOut::Disconnected(*peer_id));
+        #[cfg(feature = "metrics")]
+        self.metrics.peer_disconnected();
 
         self.kademlia.inject_disconnected(peer_id)
     }
@@ -186,6 +494,8 @@ impl NetworkBehaviour for DiscoveryBehaviour {
         failed_addresses: Option<&Vec<Multiaddr>>,
     ) {
         self.num_connections += 1;
+        #[cfg(feature = "metrics")]
+        self.metrics.connection_established();
 
         self.kademlia
             .inject_connection_established(peer_id, conn, endpoint, failed_addresses)
@@ -199,6 +509,8 @@ impl NetworkBehaviour for DiscoveryBehaviour {
         handler: <Self::ProtocolsHandler as IntoProtocolsHandler>::Handler,
     ) {
         self.num_connections -= 1;
+        #[cfg(feature = "metrics")]
+        self.metrics.connection_closed();
 
         self.kademlia
             .inject_connection_closed(peer_id, conn, endpoint, handler)
@@ -222,6 +534,24 @@ impl NetworkBehaviour for DiscoveryBehaviour {
         handler: Self::ProtocolsHandler,
         err: &DialError,
     ) {
+        if let Some(peer_id) = peer_id {
+            self.record_dial_failure(peer_id, Instant::now());
+
+            if self.user_defined.iter().any(|(p, _)| *p == peer_id) {
+                self.unreachable_boot_peers.insert(peer_id);
+
+                // Boot peers get until the deadline to connect, e.g. if they are being restarted.
+                let all_failed = self
+                    .user_defined
+                    .iter()
+                    .all(|(p, _)| self.unreachable_boot_peers.contains(p));
+                if all_failed && self.boot_peers_deadline.is_none() && !self.no_boot_peers_reported
+                {
+                    self.boot_peers_deadline = Some(Delay::new(self.boot_peers_timeout));
+                }
+            }
+        }
+
         self.kademlia.inject_dial_failure(peer_id, handler, err)
     }
 
@@ -251,16 +581,45 @@ impl NetworkBehaviour for DiscoveryBehaviour {
         cx: &mut Context,
         params: &mut impl PollParameters,
     ) -> Poll<NetworkBehaviourAction<Self::OutEvent, Self::ProtocolsHandler>> {
+        self.poll_boot_peers_deadline(cx);
+
         // Immediately process the content of `discovered`.
         if let Some(ev) = self.pending_events.pop_front() {
             return Poll::Ready(NetworkBehaviourAction::GenerateEvent(ev));
         }
 
+        // Retry Kademlia bootstrap until a boot peer is reached.
+        while let Some(next_attempt) = self.bootstrap.next_attempt.as_mut() {
+            if next_attempt.poll_unpin(cx).is_pending() {
+                break;
+            }
+            self.retry_bootstrap();
+        }
+
         // Poll Kademlia.
         while let Poll::Ready(ev) = self.kademlia.poll(cx, params) {
             match ev {
                 NetworkBehaviourAction::GenerateEvent(ev) => match ev {
-                    KademliaEvent::RoutingUpdated { .. } => {}
+                    KademliaEvent::OutboundQueryCompleted {
+                        result: QueryResult::Bootstrap(Err(e)),
+                        ..
+                    } => {
+                        debug!("Kademlia bootstrap query failed: {:?}", e);
+                        self.bootstrap.failed = true;
+                    }
+                    KademliaEvent::RoutingUpdated {
+                        peer,
+                        addresses,
+                        old_peer,
+                        ..
+                    } => {
+                        if let Some(evicted) = old_peer {
+                            self.discovered_addresses.remove(&evicted);
+                        }
+                        for addr in addresses.iter() {
+                            self.add_discovered_address(peer, addr.clone());
+                        }
+                    }
                     KademliaEvent::RoutablePeer { .. } => {}
                     KademliaEvent::PendingRoutablePeer { .. } => {}
                     other => {
@@ -317,12 +676,17 @@ impl NetworkBehaviour for DiscoveryBehaviour {
                     MdnsEvent::Discovered(list) => {
                         // Add any discovered peers to Kademlia
                         for (peer_id, multiaddr) in list {
+                            self.add_discovered_address(peer_id, multiaddr.clone());
                             if let Some(kad) = self.kademlia.as_mut() {
                                 kad.add_address(&peer_id, multiaddr);
                             }
                         }
                     }
-                    MdnsEvent::Expired(_) => {}
+                    MdnsEvent::Expired(list) => {
+                        for (peer_id, multiaddr) in list {
+                            self.remove_discovered_address(&peer_id, &multiaddr);
+                        }
+                    }
                 },
                 NetworkBehaviourAction::DialAddress { .. } => {}
                 NetworkBehaviourAction::DialPeer { .. } => {}
@@ -356,3 +720,336 @@ impl NetworkBehaviour for DiscoveryBehaviour {
         Poll::Pending
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::discovery::{
+        next_bootstrap_delay, DiscoveryBehaviour, DiscoveryError, DiscoveryOut,
+    };
+    use crate::{MultiaddrWithPeerId, Params, RoomArgs, RoomId};
+    use libp2p::identity::Keypair;
+    use libp2p::swarm::{DialError, NetworkBehaviour};
+    use libp2p::{Multiaddr, PeerId};
+    use std::task::Context;
+    use std::time::{Duration, Instant};
+
+    fn peer_id() -> PeerId {
+        Keypair::generate_ed25519().public().to_peer_id()
+    }
+
+    fn boot_peer(port: u16) -> MultiaddrWithPeerId {
+        MultiaddrWithPeerId {
+            multiaddr: format!("/ip4/127.0.0.1/tcp/{}", port).parse().unwrap(),
+            peer_id: peer_id(),
+        }
+    }
+
+    fn params() -> Params {
+        Params {
+            listen_address: "/ip4/127.0.0.1/tcp/0".parse().unwrap(),
+            mdns: false,
+            kademlia: true,
+            kademlia_bootstrap_max_retries: 0,
+            kademlia_bootstrap_base_delay: Duration::from_secs(1),
+            kademlia_bootstrap_max_delay: Duration::from_secs(60),
+            boot_peers_timeout: Duration::from_secs(30),
+            kademlia_store: None,
+            kad_protocol_name: None,
+            dial_failure_threshold: 3,
+            dial_failure_window: Duration::from_secs(60),
+            dial_failure_cooldown: Duration::from_secs(300),
+            rooms: vec![],
+        }
+    }
+
+    #[test]
+    fn bootstrap_delay_capped() {
+        let max_delay = Duration::from_secs(60);
+
+        assert_eq!(
+            next_bootstrap_delay(Duration::from_secs(1), max_delay),
+            Duration::from_secs(2)
+        );
+        assert_eq!(
+            next_bootstrap_delay(Duration::from_secs(40), max_delay),
+            max_delay
+        );
+        assert_eq!(next_bootstrap_delay(Duration::MAX, max_delay), max_delay);
+    }
+
+    #[test]
+    fn kademlia_protocol_name() {
+        let local_key = Keypair::generate_ed25519().public();
+
+        let staging = DiscoveryBehaviour::new(
+            local_key.clone(),
+            Params {
+                kad_protocol_name: Some("/mpc/staging/kad/1.0.0".to_string()),
+                ..params()
+            },
+        );
+        let production = DiscoveryBehaviour::new(
+            local_key,
+            Params {
+                kad_protocol_name: Some("/mpc/production/kad/1.0.0".to_string()),
+                ..params()
+            },
+        );
+
+        assert_eq!(
+            staging.kademlia.as_ref().unwrap().protocol_name(),
+            b"/mpc/staging/kad/1.0.0"
+        );
+        assert_eq!(
+            production.kademlia.as_ref().unwrap().protocol_name(),
+            b"/mpc/production/kad/1.0.0"
+        );
+    }
+
+    fn boot_peers_discovery(boot_peers: Vec<MultiaddrWithPeerId>) -> DiscoveryBehaviour {
+        DiscoveryBehaviour::new(
+            Keypair::generate_ed25519().public(),
+            Params {
+                boot_peers_timeout: Duration::from_millis(50),
+                rooms: vec![RoomArgs {
+                    id: RoomId::from("test".to_string()),
+                    max_size: 3,
+                    boot_peers,
+                    inbound_queue: None,
+                }],
+                ..params()
+            },
+        )
+    }
+
+    fn fail_dial(discovery: &mut DiscoveryBehaviour, peer_id: PeerId) {
+        let handler = discovery.new_handler();
+        discovery.inject_dial_failure(Some(peer_id), handler, &DialError::NoAddresses);
+    }
+
+    fn num_reported(discovery: &DiscoveryBehaviour) -> usize {
+        discovery
+            .pending_events
+            .iter()
+            .filter(|e| matches!(e, DiscoveryOut::NoBootPeersReachable))
+            .count()
+    }
+
+    #[test]
+    fn no_boot_peers_reachable() {
+        let boot_peers = vec![boot_peer(4001), boot_peer(4002)];
+        let mut discovery = boot_peers_discovery(boot_peers.clone());
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+
+        // Failed dials alone don't report the boot peers before the deadline.
+        fail_dial(&mut discovery, boot_peers[0].peer_id);
+        fail_dial(&mut discovery, boot_peers[1].peer_id);
+        discovery.poll_boot_peers_deadline(&mut cx);
+        assert_eq!(num_reported(&discovery), 0);
+
+        std::thread::sleep(Duration::from_millis(100));
+        discovery.poll_boot_peers_deadline(&mut cx);
+        fail_dial(&mut discovery, boot_peers[0].peer_id);
+        discovery.poll_boot_peers_deadline(&mut cx);
+        assert_eq!(num_reported(&discovery), 1);
+    }
+
+    #[test]
+    fn boot_peer_connected_after_another_failed() {
+        let boot_peers = vec![boot_peer(4001), boot_peer(4002)];
+        let mut discovery = boot_peers_discovery(boot_peers.clone());
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+
+        fail_dial(&mut discovery, boot_peers[0].peer_id);
+        discovery.inject_connected(&boot_peers[1].peer_id);
+
+        std::thread::sleep(Duration::from_millis(100));
+        discovery.poll_boot_peers_deadline(&mut cx);
+        assert_eq!(num_reported(&discovery), 0);
+    }
+
+    #[test]
+    fn no_boot_peers_connected_in_time() {
+        let boot_peer = boot_peer(4001);
+        let mut discovery = boot_peers_discovery(vec![boot_peer]);
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+
+        discovery.poll_boot_peers_deadline(&mut cx);
+        assert_eq!(num_reported(&discovery), 0);
+
+        std::thread::sleep(Duration::from_millis(100));
+        discovery.poll_boot_peers_deadline(&mut cx);
+        assert_eq!(num_reported(&discovery), 1);
+    }
+
+    #[test]
+    fn blacklisted_after_repeated_dial_failures() {
+        let boot_peer = boot_peer(4001);
+        let peer_id = peer_id();
+        let mut discovery = DiscoveryBehaviour::new(
+            Keypair::generate_ed25519().public(),
+            Params {
+                rooms: vec![RoomArgs {
+                    id: RoomId::from("test".to_string()),
+                    max_size: 3,
+                    boot_peers: vec![boot_peer.clone()],
+                    inbound_queue: None,
+                }],
+                ..params()
+            },
+        );
+        discovery
+            .kademlia
+            .as_mut()
+            .unwrap()
+            .add_address(&peer_id, "/ip4/127.0.0.1/tcp/4002".parse().unwrap());
+        assert!(!discovery.addresses_of_peer(&peer_id).is_empty());
+
+        for _ in 0..3 {
+            let handler = discovery.new_handler();
+            discovery.inject_dial_failure(Some(peer_id), handler, &DialError::NoAddresses);
+            let handler = discovery.new_handler();
+            discovery.inject_dial_failure(
+                Some(boot_peer.peer_id),
+                handler,
+                &DialError::NoAddresses,
+            );
+        }
+
+        assert!(discovery.addresses_of_peer(&peer_id).is_empty());
+        assert!(discovery
+            .addresses_of_peer(&boot_peer.peer_id)
+            .contains(&boot_peer.multiaddr));
+
+        let cooldown_expired = Instant::now() + Duration::from_secs(300);
+        assert!(!discovery.is_blacklisted(&peer_id, cooldown_expired));
+    }
+
+    #[test]
+    fn blacklisting_lifted_on_connection() {
+        let peer_id = peer_id();
+        let mut discovery = DiscoveryBehaviour::new(Keypair::generate_ed25519().public(), params());
+        discovery
+            .kademlia
+            .as_mut()
+            .unwrap()
+            .add_address(&peer_id, "/ip4/127.0.0.1/tcp/4002".parse().unwrap());
+
+        for _ in 0..3 {
+            let handler = discovery.new_handler();
+            discovery.inject_dial_failure(Some(peer_id), handler, &DialError::NoAddresses);
+        }
+        assert!(discovery.addresses_of_peer(&peer_id).is_empty());
+
+        // Peer connects inbound while still in its cooldown.
+        discovery.inject_connected(&peer_id);
+        assert!(!discovery.is_blacklisted(&peer_id, Instant::now()));
+        assert!(!discovery.addresses_of_peer(&peer_id).is_empty());
+    }
+
+    #[test]
+    fn reserved_peer_address_known() {
+        let boot_peer = boot_peer(4001);
+        let room = |name: &str| RoomArgs {
+            id: RoomId::from(name.to_string()),
+            max_size: 3,
+            boot_peers: vec![boot_peer.clone()],
+            inbound_queue: None,
+        };
+        // Boot peer is shared by both rooms, but its address should be listed once.
+        let discovery = DiscoveryBehaviour::new(
+            Keypair::generate_ed25519().public(),
+            Params {
+                rooms: vec![room("keygen"), room("keysign")],
+                ..params()
+            },
+        );
+
+        assert_eq!(
+            discovery.known_addresses(&boot_peer.peer_id),
+            vec![boot_peer.multiaddr]
+        );
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn rediscovered_peer_counted_once() {
+        use crate::metrics::{Metrics, MetricsVisitor};
+
+        struct Discovered(u64);
+
+        impl MetricsVisitor for Discovered {
+            fn gauge(&mut self, _name: &'static str, _help: &'static str, _value: u64) {}
+
+            fn counter(&mut self, name: &'static str, _help: &'static str, value: u64) {
+                if name == "mpc_p2p_peers_discovered_total" {
+                    self.0 = value;
+                }
+            }
+        }
+
+        let mut discovery = DiscoveryBehaviour::new(Keypair::generate_ed25519().public(), params());
+        let peer_id = peer_id();
+        discovery.add_discovered_address(peer_id, "/ip4/127.0.0.1/tcp/4001".parse().unwrap());
+        discovery.add_discovered_address(peer_id, "/ip4/127.0.0.1/tcp/4001".parse().unwrap());
+        discovery.add_discovered_address(peer_id, "/ip4/127.0.0.1/tcp/4002".parse().unwrap());
+
+        let mut discovered = Discovered(0);
+        discovery.metrics().visit(&mut discovered);
+        assert_eq!(discovered.0, 1);
+    }
+
+    #[test]
+    fn discovered_addresses_pruned() {
+        let mut discovery = DiscoveryBehaviour::new(Keypair::generate_ed25519().public(), params());
+        let peer_id = peer_id();
+        let addresses: Vec<Multiaddr> = vec![
+            "/ip4/127.0.0.1/tcp/4001".parse().unwrap(),
+            "/ip4/127.0.0.1/tcp/4002".parse().unwrap(),
+        ];
+
+        for addr in addresses.iter() {
+            discovery.add_discovered_address(peer_id, addr.clone());
+        }
+        discovery.remove_discovered_address(&peer_id, &addresses[0]);
+        assert_eq!(
+            discovery.known_addresses(&peer_id),
+            vec![addresses[1].clone()]
+        );
+        discovery.remove_discovered_address(&peer_id, &addresses[1]);
+        assert!(!discovery.discovered_addresses.contains_key(&peer_id));
+
+        // Blacklisted peer's addresses are forgotten too.
+        discovery.add_discovered_address(peer_id, addresses[0].clone());
+        for _ in 0..3 {
+            let handler = discovery.new_handler();
+            discovery.inject_dial_failure(Some(peer_id), handler, &DialError::NoAddresses);
+        }
+        assert!(!discovery.discovered_addresses.contains_key(&peer_id));
+    }
+
+    #[test]
+    fn bootstrap_errors() {
+        let local_key = Keypair::generate_ed25519().public();
+
+        let mut disabled = DiscoveryBehaviour::new(
+            local_key.clone(),
+            Params {
+                kademlia: false,
+                ..params()
+            },
+        );
+        let err = disabled.bootstrap().unwrap_err();
+        assert!(matches!(err, DiscoveryError::KademliaDisabled));
+        assert_eq!(err.to_string(), "Kademlia is not activated");
+
+        let mut no_peers = DiscoveryBehaviour::new(local_key, params());
+        assert!(matches!(
+            no_peers.bootstrap(),
+            Err(DiscoveryError::BootstrapFailed(_))
+        ));
+    }
+}
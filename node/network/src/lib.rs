@@ -8,11 +8,16 @@ mod config;
 mod discovery;
 mod error;
 mod messages;
+#[cfg(feature = "metrics")]
+pub mod metrics;
 mod service;
+mod store;
 
 pub use self::config::*;
+pub use self::discovery::DiscoveryError;
 pub use self::messages::*;
 pub use self::service::*;
+pub use self::store::*;
 use std::borrow::Cow;
 
 use arrayvec::ArrayString;
@@ -20,6 +25,10 @@ use arrayvec::ArrayString;
 /// The maximum allowed number of established connections per peer.
 const MAX_CONNECTIONS_PER_PEER: usize = 2;
 
+/// Version of the room protocol wire format, bumped on every change to it, so that the peers
+/// that can't parse each other's messages don't negotiate the protocol at all.
+const ROOM_PROTOCOL_VERSION: u32 = 2;
+
 /// Identifier of a room in the peerset.
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct RoomId(ArrayString<64>);
@@ -34,6 +43,6 @@ impl RoomId {
     }
 
     pub fn as_protocol_id(&self) -> Cow<'static, str> {
-        Cow::Owned(format!("/room/{}", self.0.to_string()))
+        Cow::Owned(format!("/room/{}/{ROOM_PROTOCOL_VERSION}", self.0))
     }
 }
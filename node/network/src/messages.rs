@@ -13,6 +13,8 @@ pub struct MessageContext {
     pub message_type: MessageType,
     pub session_id: u64,
     pub protocol_id: u64,
+    /// Monotonic number of the message in the sender's stream within a session.
+    pub seq: u64,
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -50,8 +52,11 @@ impl RequestResponseCodec for GenericCodec {
         {
             0 => MessageType::Coordination,
             1 => MessageType::Computation,
-            _ => {
-                panic!("unknown messages type");
+            t => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Unknown message type: {}", t),
+                ));
             }
         };
 
@@ -67,6 +72,10 @@ impl RequestResponseCodec for GenericCodec {
             .await
             .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
 
+        let seq = unsigned_varint::aio::read_u64(&mut io)
+            .await
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+
         // Read the length.
         let length = unsigned_varint::aio::read_usize(&mut io)
             .await
@@ -91,6 +100,7 @@ impl RequestResponseCodec for GenericCodec {
                 message_type,
                 session_id,
                 protocol_id,
+                seq,
             },
             payload: buffer,
             is_broadcast: is_broadcast != 0,
@@ -181,6 +191,13 @@ impl RequestResponseCodec for GenericCodec {
             .await?;
         }
 
+        // Write sequence number
+        {
+            let mut buffer = unsigned_varint::encode::u64_buffer();
+            io.write_all(unsigned_varint::encode::u64(req.context.seq, &mut buffer))
+                .await?;
+        }
+
         // Write the length.
         {
             let mut buffer = unsigned_varint::encode::usize_buffer();
@@ -225,3 +242,22 @@ impl RequestResponseCodec for GenericCodec {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::messages::GenericCodec;
+    use futures::io::Cursor;
+    use libp2p::request_response::RequestResponseCodec;
+
+    #[async_std::test]
+    async fn unknown_message_type_rejected() {
+        let mut codec = GenericCodec {
+            max_request_size: 1024,
+            max_response_size: 1024,
+        };
+        // Message type followed by the broadcast marker, ids, sequence number and empty payload.
+        let mut io = Cursor::new(vec![7, 0, 0, 0, 0, 0]);
+
+        assert!(codec.read_request(&vec![], &mut io).await.is_err());
+    }
+}
@@ -0,0 +1,109 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Receives the current values of the metrics, e.g. to register them with a Prometheus exporter.
+pub trait MetricsVisitor {
+    /// Value that can arbitrarily go up and down.
+    fn gauge(&mut self, name: &'static str, help: &'static str, value: u64);
+
+    /// Value that only ever increases.
+    fn counter(&mut self, name: &'static str, help: &'static str, value: u64);
+}
+
+/// Source of the metrics that can be scraped with a [`MetricsVisitor`].
+pub trait Metrics: Send + Sync {
+    fn visit(&self, visitor: &mut dyn MetricsVisitor);
+}
+
+/// Metrics updated by the `DiscoveryBehaviour`.
+#[derive(Debug, Default)]
+pub struct DiscoveryMetrics {
+    num_connections: AtomicU64,
+    connects: AtomicU64,
+    disconnects: AtomicU64,
+    discovered: AtomicU64,
+}
+
+impl DiscoveryMetrics {
+    pub(crate) fn connection_established(&self) {
+        self.num_connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn connection_closed(&self) {
+        self.num_connections.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn peer_connected(&self) {
+        self.connects.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn peer_disconnected(&self) {
+        self.disconnects.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn peer_discovered(&self) {
+        self.discovered.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl Metrics for DiscoveryMetrics {
+    fn visit(&self, visitor: &mut dyn MetricsVisitor) {
+        visitor.gauge(
+            "mpc_p2p_num_connections",
+            "Number of established connections",
+            self.num_connections.load(Ordering::Relaxed),
+        );
+        visitor.counter(
+            "mpc_p2p_peers_connected_total",
+            "Number of times a peer has connected",
+            self.connects.load(Ordering::Relaxed),
+        );
+        visitor.counter(
+            "mpc_p2p_peers_disconnected_total",
+            "Number of times a peer has disconnected",
+            self.disconnects.load(Ordering::Relaxed),
+        );
+        visitor.counter(
+            "mpc_p2p_peers_discovered_total",
+            "Number of peers discovered through Kademlia and mDNS",
+            self.discovered.load(Ordering::Relaxed),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::metrics::{DiscoveryMetrics, Metrics, MetricsVisitor};
+    use std::collections::HashMap;
+
+    #[derive(Default)]
+    struct Collector(HashMap<&'static str, u64>);
+
+    impl MetricsVisitor for Collector {
+        fn gauge(&mut self, name: &'static str, _help: &'static str, value: u64) {
+            self.0.insert(name, value);
+        }
+
+        fn counter(&mut self, name: &'static str, _help: &'static str, value: u64) {
+            self.0.insert(name, value);
+        }
+    }
+
+    #[test]
+    fn discovery_metrics_visit() {
+        let metrics = DiscoveryMetrics::default();
+        metrics.connection_established();
+        metrics.connection_established();
+        metrics.connection_closed();
+        metrics.peer_connected();
+        metrics.peer_discovered();
+        metrics.peer_discovered();
+
+        let mut collector = Collector::default();
+        metrics.visit(&mut collector);
+
+        assert_eq!(collector.0["mpc_p2p_num_connections"], 1);
+        assert_eq!(collector.0["mpc_p2p_peers_connected_total"], 1);
+        assert_eq!(collector.0["mpc_p2p_peers_disconnected_total"], 0);
+        assert_eq!(collector.0["mpc_p2p_peers_discovered_total"], 2);
+    }
+}
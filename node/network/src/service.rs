@@ -1,26 +1,53 @@
 use crate::broadcast::IfDisconnected;
 use crate::error::Error;
+#[cfg(feature = "metrics")]
+use crate::metrics::DiscoveryMetrics;
 use crate::{
     behaviour::{Behaviour, BehaviourOut},
-    broadcast, MessageContext, NodeKeyConfig, RoomId,
+    broadcast, DiscoveryError, MessageContext, NodeKeyConfig, RoomId,
 };
 use async_std::channel::{unbounded, Receiver, Sender};
-use futures::channel::mpsc;
+use futures::channel::{mpsc, oneshot};
 use futures::select;
 use futures_util::stream::StreamExt;
 use libp2p::core::transport::upgrade;
 use libp2p::noise::NoiseConfig;
-use libp2p::swarm::SwarmEvent;
+use libp2p::swarm::{DialError, SwarmEvent};
 use libp2p::tcp::TcpConfig;
-use libp2p::{mplex, noise, PeerId, Swarm, Transport};
-use log::{info, warn};
+use libp2p::{mplex, noise, Multiaddr, PeerId, Swarm, Transport};
+use log::{debug, info, warn};
 use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
 
 /// Events emitted by this Service.
 #[allow(clippy::large_enum_variant)]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum NetworkEvent {
     BroadcastMessage(PeerId, Cow<'static, str>),
+    /// Connection with the peer has been established.
+    PeerConnected(PeerId),
+    /// Connection with the peer has been closed.
+    PeerDisconnected(PeerId),
+    /// Dialing every boot peer has failed, the node is unlikely to join the network on its own.
+    NoBootPeersReachable,
+}
+
+/// Time to wait for the dial of the probed peer to complete.
+pub const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Outcome of probing the connectivity with a peer.
+#[derive(Debug, Clone)]
+pub enum ProbeResult {
+    /// Peer was already connected.
+    Connected,
+    /// Peer was dialed successfully at the given address.
+    Reachable(Multiaddr),
+    /// Dialing the peer has failed, with the dial error.
+    Unreachable(Arc<DialError>),
+    /// Dial hasn't completed within [`PROBE_TIMEOUT`].
+    TimedOut,
 }
 
 /// Messages into the service to handle.
@@ -31,6 +58,18 @@ pub enum NetworkMessage {
         context: MessageContext,
         message: MessageRouting,
     },
+    /// Subscribes to the events emitted by the worker.
+    EventStream(mpsc::UnboundedSender<NetworkEvent>),
+    /// Requests the peers currently connected to the node.
+    ConnectedPeers(oneshot::Sender<HashSet<PeerId>>),
+    /// Requests the addresses the node is listening on.
+    ListenAddresses(oneshot::Sender<Vec<Multiaddr>>),
+    /// Requests the addresses currently known for the peer.
+    KnownAddresses(PeerId, oneshot::Sender<Vec<Multiaddr>>),
+    /// Closes all connections with the peer.
+    DisconnectPeer(PeerId),
+    /// Dials the peer to check whether it can be reached.
+    ProbePeer(PeerId, oneshot::Sender<ProbeResult>),
 }
 
 #[derive(Debug)]
@@ -64,6 +103,9 @@ pub struct NetworkService {
     local_peer_id: PeerId,
     /// Channel for sending requests to worker.
     to_worker: Sender<NetworkMessage>,
+    /// Metrics of the discovery behaviour.
+    #[cfg(feature = "metrics")]
+    discovery_metrics: Arc<DiscoveryMetrics>,
 }
 
 impl NetworkWorker {
@@ -116,6 +158,9 @@ impl NetworkWorker {
             warn!(target: "sub-libp2p", "Can't listen on 'listen_address' because: {:?}", err)
         }
 
+        #[cfg(feature = "metrics")]
+        let discovery_metrics = swarm.behaviour().discovery_metrics();
+
         let (network_sender_in, network_receiver_in) = unbounded();
 
         let worker = NetworkWorker {
@@ -127,6 +172,8 @@ impl NetworkWorker {
         let service = NetworkService {
             local_peer_id,
             to_worker: network_sender_in,
+            #[cfg(feature = "metrics")]
+            discovery_metrics,
         };
 
         Ok((worker, service))
@@ -135,12 +182,20 @@ impl NetworkWorker {
     /// Starts the libp2p service networking stack.
     pub async fn run(mut self) {
         // Bootstrap with Kademlia
-        if let Err(e) = self.swarm.behaviour_mut().bootstrap() {
-            warn!("Failed to bootstrap with Kademlia: {}", e);
+        match self.swarm.behaviour_mut().bootstrap() {
+            Ok(_) => {}
+            Err(DiscoveryError::KademliaDisabled) => {
+                debug!("Kademlia is disabled, skipping bootstrap")
+            }
+            Err(e) => warn!("Failed to bootstrap with Kademlia: {}", e),
         }
 
         let mut swarm_stream = self.swarm.fuse();
         let mut network_stream = self.from_service.fuse();
+        let mut event_streams = Vec::<mpsc::UnboundedSender<NetworkEvent>>::new();
+        let mut pending_probes = HashMap::<PeerId, Vec<oneshot::Sender<ProbeResult>>>::new();
+        // Peers connected only for probing, whose connection events aren't meant for subscribers.
+        let mut probed_peers = HashSet::<PeerId>::new();
 
         loop {
             select! {
@@ -150,9 +205,41 @@ impl NetworkWorker {
                         SwarmEvent::Behaviour(BehaviourOut::InboundMessage{peer, protocol}) => {
                             info!("Inbound message from {:?} related to {:?} protocol", peer, protocol);
                         },
+                        SwarmEvent::Behaviour(BehaviourOut::PeerConnected(peer_id)) => {
+                            if !probed_peers.contains(&peer_id) {
+                                emit_event(&mut event_streams, NetworkEvent::PeerConnected(peer_id));
+                            }
+                        },
+                        SwarmEvent::Behaviour(BehaviourOut::PeerDisconnected(peer_id)) => {
+                            if !probed_peers.remove(&peer_id) {
+                                emit_event(&mut event_streams, NetworkEvent::PeerDisconnected(peer_id));
+                            }
+                        },
+                        SwarmEvent::Behaviour(BehaviourOut::NoBootPeersReachable) => {
+                            emit_event(&mut event_streams, NetworkEvent::NoBootPeersReachable);
+                        },
                         SwarmEvent::NewListenAddr { address, .. } => info!("Listening on {:?}", address),
-                        SwarmEvent::ConnectionEstablished { peer_id: _, .. } => {
-
+                        // Inbound connections don't tell whether the peer can be dialed.
+                        SwarmEvent::ConnectionEstablished { peer_id, endpoint, num_established, .. } if endpoint.is_dialer() => {
+                            if let Some(probes) = pending_probes.remove(&peer_id) {
+                                let address = endpoint.get_remote_address().clone();
+                                for tx in probes {
+                                    let _ = tx.send(ProbeResult::Reachable(address.clone()));
+                                }
+                                // Probing shouldn't leave behind a connection that wasn't open.
+                                if num_established.get() == 1 {
+                                    probed_peers.insert(peer_id);
+                                    let _ = swarm_stream.get_mut().disconnect_peer_id(peer_id);
+                                }
+                            }
+                        },
+                        SwarmEvent::OutgoingConnectionError { peer_id: Some(peer_id), error } => {
+                            if let Some(probes) = pending_probes.remove(&peer_id) {
+                                let error = Arc::new(error);
+                                for tx in probes {
+                                    let _ = tx.send(ProbeResult::Unreachable(error.clone()));
+                                }
+                            }
                         },
                         SwarmEvent::ConnectionClosed { peer_id: _, .. } => { }
                         _ => continue
@@ -204,6 +291,38 @@ impl NetworkWorker {
                                     }
                                 }
                             }
+                            NetworkMessage::EventStream(tx) => {
+                                event_streams.push(tx);
+                            }
+                            NetworkMessage::ConnectedPeers(tx) => {
+                                let _ = tx.send(swarm_stream.get_ref().connected_peers().cloned().collect());
+                            }
+                            NetworkMessage::DisconnectPeer(peer_id) => {
+                                if swarm_stream.get_mut().disconnect_peer_id(peer_id).is_err() {
+                                    warn!("Peer {} to disconnect isn't connected", peer_id);
+                                }
+                            }
+                            NetworkMessage::ProbePeer(peer_id, tx) => {
+                                let swarm = swarm_stream.get_mut();
+                                if swarm.is_connected(&peer_id) {
+                                    let _ = tx.send(ProbeResult::Connected);
+                                } else if let Err(e) = swarm.dial(&peer_id) {
+                                    let _ = tx.send(ProbeResult::Unreachable(Arc::new(e)));
+                                } else {
+                                    // Forget the probes that have timed out.
+                                    pending_probes.retain(|_, probes| {
+                                        probes.retain(|tx| !tx.is_canceled());
+                                        !probes.is_empty()
+                                    });
+                                    pending_probes.entry(peer_id).or_default().push(tx);
+                                }
+                            }
+                            NetworkMessage::ListenAddresses(tx) => {
+                                let _ = tx.send(swarm_stream.get_ref().listeners().cloned().collect());
+                            }
+                            NetworkMessage::KnownAddresses(peer_id, tx) => {
+                                let _ = tx.send(swarm_stream.get_ref().behaviour().known_addresses(&peer_id));
+                            }
                         }
                     }
                     None => { break; }
@@ -213,6 +332,11 @@ impl NetworkWorker {
     }
 }
 
+/// Sends event to every subscriber, dropping the ones that are no longer interested.
+fn emit_event(streams: &mut Vec<mpsc::UnboundedSender<NetworkEvent>>, event: NetworkEvent) {
+    streams.retain(|tx| tx.unbounded_send(event.clone()).is_ok());
+}
+
 impl NetworkService {
     pub async fn broadcast_message(
         &self,
@@ -320,7 +444,195 @@ impl NetworkService {
             .expect("expected worker worker channel to not be full");
     }
 
+    /// Returns a stream of the events emitted by the network worker.
+    pub fn event_stream(&self) -> mpsc::UnboundedReceiver<NetworkEvent> {
+        let (tx, rx) = mpsc::unbounded();
+        let _ = self.to_worker.try_send(NetworkMessage::EventStream(tx));
+        rx
+    }
+
+    /// Returns peers the node is currently connected to.
+    pub async fn connected_peers(&self) -> HashSet<PeerId> {
+        let (tx, rx) = oneshot::channel();
+        self.to_worker
+            .send(NetworkMessage::ConnectedPeers(tx))
+            .await
+            .expect("expected worker channel to not be full");
+
+        rx.await.unwrap_or_default()
+    }
+
+    /// Returns addresses the node is listening on.
+    pub async fn listen_addresses(&self) -> Vec<Multiaddr> {
+        let (tx, rx) = oneshot::channel();
+        self.to_worker
+            .send(NetworkMessage::ListenAddresses(tx))
+            .await
+            .expect("expected worker channel to not be full");
+
+        rx.await.unwrap_or_default()
+    }
+
+    /// Returns addresses currently known for the peer, e.g. for diagnostics or manual dialing.
+    pub async fn known_addresses(&self, peer_id: PeerId) -> Vec<Multiaddr> {
+        let (tx, rx) = oneshot::channel();
+        self.to_worker
+            .send(NetworkMessage::KnownAddresses(peer_id, tx))
+            .await
+            .expect("expected worker channel to not be full");
+
+        rx.await.unwrap_or_default()
+    }
+
+    /// Checks whether the peer can be reached by dialing its known addresses, closing the
+    /// connection afterwards unless it was already open. Gives up after [`PROBE_TIMEOUT`].
+    ///
+    /// Connection opened by the probe isn't reported as [`NetworkEvent::PeerConnected`] nor
+    /// [`NetworkEvent::PeerDisconnected`], so it can't be used to connect the peer.
+    pub async fn probe_peer(&self, peer_id: PeerId) -> ProbeResult {
+        let (tx, rx) = oneshot::channel();
+        self.to_worker
+            .send(NetworkMessage::ProbePeer(peer_id, tx))
+            .await
+            .expect("expected worker channel to not be full");
+
+        match async_std::future::timeout(PROBE_TIMEOUT, rx).await {
+            Ok(result) => result.expect("expected worker to answer the probe"),
+            Err(_) => ProbeResult::TimedOut,
+        }
+    }
+
+    /// Closes all connections with the peer.
+    pub async fn disconnect_peer(&self, peer_id: PeerId) {
+        self.to_worker
+            .send(NetworkMessage::DisconnectPeer(peer_id))
+            .await
+            .expect("expected worker channel to not be full");
+    }
+
+    /// Returns connection and discovery metrics of this node.
+    #[cfg(feature = "metrics")]
+    pub fn discovery_metrics(&self) -> Arc<DiscoveryMetrics> {
+        self.discovery_metrics.clone()
+    }
+
     pub fn local_peer_id(&self) -> PeerId {
         self.local_peer_id.clone()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        broadcast, MultiaddrWithPeerId, NetworkService, NetworkWorker, NodeKeyConfig, Params,
+        ProbeResult, RoomArgs,
+    };
+    use futures::channel::mpsc;
+    use libp2p::identity::Keypair;
+    use libp2p::Multiaddr;
+    use std::net::TcpListener;
+    use std::time::Duration;
+
+    fn params(
+        boot_peers: Vec<MultiaddrWithPeerId>,
+    ) -> (Params, mpsc::Receiver<broadcast::IncomingMessage>) {
+        let (_, room, room_rx) = RoomArgs::new_full("probe".to_string(), boot_peers.into_iter(), 3);
+        let params = Params {
+            listen_address: "/ip4/127.0.0.1/tcp/0".parse().unwrap(),
+            mdns: false,
+            kademlia: false,
+            kademlia_bootstrap_max_retries: 0,
+            kademlia_bootstrap_base_delay: Duration::from_secs(1),
+            kademlia_bootstrap_max_delay: Duration::from_secs(60),
+            boot_peers_timeout: Duration::from_secs(30),
+            kademlia_store: None,
+            kad_protocol_name: None,
+            dial_failure_threshold: 0,
+            dial_failure_window: Duration::from_secs(60),
+            dial_failure_cooldown: Duration::from_secs(300),
+            rooms: vec![room],
+        };
+
+        (params, room_rx)
+    }
+
+    /// Waits until the node reports the address it listens on.
+    async fn listen_address(service: &NetworkService) -> Multiaddr {
+        loop {
+            if let Some(address) = service.listen_addresses().await.into_iter().next() {
+                return address;
+            }
+            async_std::task::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    #[async_std::test]
+    async fn probe_reachable_and_unreachable() {
+        let (remote_params, _remote_rx) = params(vec![]);
+        let (remote_worker, remote_service) =
+            NetworkWorker::new(NodeKeyConfig::default(), remote_params).unwrap();
+        async_std::task::spawn(remote_worker.run());
+        let remote = MultiaddrWithPeerId {
+            multiaddr: listen_address(&remote_service).await,
+            peer_id: remote_service.local_peer_id(),
+        };
+
+        // Port is released right away, so nobody is listening on it.
+        let closed_port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let offline = MultiaddrWithPeerId {
+            multiaddr: format!("/ip4/127.0.0.1/tcp/{}", closed_port)
+                .parse()
+                .unwrap(),
+            peer_id: Keypair::generate_ed25519().public().to_peer_id(),
+        };
+
+        let (local_params, _local_rx) = params(vec![remote.clone(), offline.clone()]);
+        let (local_worker, local_service) =
+            NetworkWorker::new(NodeKeyConfig::default(), local_params).unwrap();
+        async_std::task::spawn(local_worker.run());
+
+        assert!(matches!(
+            local_service.probe_peer(remote.peer_id).await,
+            ProbeResult::Reachable(address) if address == remote.multiaddr
+        ));
+        assert!(matches!(
+            local_service.probe_peer(offline.peer_id).await,
+            ProbeResult::Unreachable(_)
+        ));
+    }
+
+    #[async_std::test]
+    async fn probe_connection_not_reported() {
+        let (remote_params, _remote_rx) = params(vec![]);
+        let (remote_worker, remote_service) =
+            NetworkWorker::new(NodeKeyConfig::default(), remote_params).unwrap();
+        async_std::task::spawn(remote_worker.run());
+        let remote = MultiaddrWithPeerId {
+            multiaddr: listen_address(&remote_service).await,
+            peer_id: remote_service.local_peer_id(),
+        };
+
+        let (local_params, _local_rx) = params(vec![remote.clone()]);
+        let (local_worker, local_service) =
+            NetworkWorker::new(NodeKeyConfig::default(), local_params).unwrap();
+        async_std::task::spawn(local_worker.run());
+        let mut events = local_service.event_stream();
+
+        assert!(matches!(
+            local_service.probe_peer(remote.peer_id).await,
+            ProbeResult::Reachable(_)
+        ));
+        // Gives the probe connection time to be closed.
+        async_std::task::sleep(Duration::from_millis(500)).await;
+
+        assert!(!local_service
+            .connected_peers()
+            .await
+            .contains(&remote.peer_id));
+        assert!(events.try_next().is_err());
+    }
+}
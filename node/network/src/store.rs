@@ -0,0 +1,285 @@
+use libp2p::kad::record::store::{self, MemoryStore, RecordStore};
+use libp2p::kad::record::{Key, ProviderRecord, Record};
+use libp2p::{Multiaddr, PeerId};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::{fs, io};
+
+/// Builds the Kademlia record store for the local peer.
+pub type StoreFactory = Arc<dyn Fn(PeerId) -> Box<dyn DiscoveryStore> + Send + Sync>;
+
+/// Object-safe counterpart of the Kademlia [`RecordStore`], so that the store can be chosen at
+/// runtime through [`Params`](crate::Params). Implemented for every [`RecordStore`].
+pub trait DiscoveryStore: Send {
+    fn get(&self, k: &Key) -> Option<Cow<'_, Record>>;
+
+    fn put(&mut self, r: Record) -> store::Result<()>;
+
+    fn remove(&mut self, k: &Key);
+
+    fn records(&self) -> Box<dyn Iterator<Item = Cow<'_, Record>> + '_>;
+
+    fn add_provider(&mut self, record: ProviderRecord) -> store::Result<()>;
+
+    fn providers(&self, key: &Key) -> Vec<ProviderRecord>;
+
+    fn provided(&self) -> Box<dyn Iterator<Item = Cow<'_, ProviderRecord>> + '_>;
+
+    fn remove_provider(&mut self, k: &Key, p: &PeerId);
+}
+
+impl<S> DiscoveryStore for S
+where
+    S: for<'a> RecordStore<'a> + Send,
+{
+    fn get(&self, k: &Key) -> Option<Cow<'_, Record>> {
+        RecordStore::get(self, k)
+    }
+
+    fn put(&mut self, r: Record) -> store::Result<()> {
+        RecordStore::put(self, r)
+    }
+
+    fn remove(&mut self, k: &Key) {
+        RecordStore::remove(self, k)
+    }
+
+    fn records(&self) -> Box<dyn Iterator<Item = Cow<'_, Record>> + '_> {
+        Box::new(RecordStore::records(self))
+    }
+
+    fn add_provider(&mut self, record: ProviderRecord) -> store::Result<()> {
+        RecordStore::add_provider(self, record)
+    }
+
+    fn providers(&self, key: &Key) -> Vec<ProviderRecord> {
+        RecordStore::providers(self, key)
+    }
+
+    fn provided(&self) -> Box<dyn Iterator<Item = Cow<'_, ProviderRecord>> + '_> {
+        Box::new(RecordStore::provided(self))
+    }
+
+    fn remove_provider(&mut self, k: &Key, p: &PeerId) {
+        RecordStore::remove_provider(self, k, p)
+    }
+}
+
+/// Type-erased record store used by the `DiscoveryBehaviour`.
+pub struct BoxedStore(Box<dyn DiscoveryStore>);
+
+impl BoxedStore {
+    pub fn new(store: Box<dyn DiscoveryStore>) -> Self {
+        Self(store)
+    }
+}
+
+impl<'a> RecordStore<'a> for BoxedStore {
+    type RecordsIter = Box<dyn Iterator<Item = Cow<'a, Record>> + 'a>;
+    type ProvidedIter = Box<dyn Iterator<Item = Cow<'a, ProviderRecord>> + 'a>;
+
+    fn get(&'a self, k: &Key) -> Option<Cow<'_, Record>> {
+        self.0.get(k)
+    }
+
+    fn put(&'a mut self, r: Record) -> store::Result<()> {
+        self.0.put(r)
+    }
+
+    fn remove(&'a mut self, k: &Key) {
+        self.0.remove(k)
+    }
+
+    fn records(&'a self) -> Self::RecordsIter {
+        self.0.records()
+    }
+
+    fn add_provider(&'a mut self, record: ProviderRecord) -> store::Result<()> {
+        self.0.add_provider(record)
+    }
+
+    fn providers(&'a self, key: &Key) -> Vec<ProviderRecord> {
+        self.0.providers(key)
+    }
+
+    fn provided(&'a self) -> Self::ProvidedIter {
+        self.0.provided()
+    }
+
+    fn remove_provider(&'a mut self, k: &Key, p: &PeerId) {
+        self.0.remove_provider(k, p)
+    }
+}
+
+/// Record store that keeps records in memory and writes them through to a file, so that they
+/// survive node restarts. Expiration times aren't persisted.
+///
+/// It can be wired into the discovery through [`Params`](crate::Params):
+///
+/// ```ignore
+/// let path = PathBuf::from("./data/kad_records");
+/// let params = Params {
+///     kademlia_store: Some(Arc::new(move |local_peer_id| {
+///         Box::new(FileStore::open(local_peer_id, &path).expect("error opening record store"))
+///     })),
+///     ..
+/// };
+/// ```
+pub struct FileStore {
+    inner: MemoryStore,
+    path: PathBuf,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredRecords {
+    records: Vec<StoredRecord>,
+    providers: Vec<StoredProvider>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredRecord {
+    key: Vec<u8>,
+    value: Vec<u8>,
+    publisher: Option<Vec<u8>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredProvider {
+    key: Vec<u8>,
+    provider: Vec<u8>,
+    addresses: Vec<Vec<u8>>,
+}
+
+impl FileStore {
+    /// Opens the store at `path`, loading the records persisted previously if there are any.
+    pub fn open(local_peer_id: PeerId, path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut inner = MemoryStore::new(local_peer_id);
+
+        if path.exists() {
+            let stored: StoredRecords = serde_ipld_dagcbor::from_slice(&fs::read(&path)?)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+
+            for r in stored.records {
+                let mut record = Record::new(Key::from(r.key), r.value);
+                record.publisher = r.publisher.and_then(|p| PeerId::from_bytes(&p).ok());
+                let _ = RecordStore::put(&mut inner, record);
+            }
+
+            for p in stored.providers {
+                let provider = match PeerId::from_bytes(&p.provider) {
+                    Ok(provider) => provider,
+                    Err(_) => continue,
+                };
+                let addresses = p
+                    .addresses
+                    .into_iter()
+                    .filter_map(|a| Multiaddr::try_from(a).ok())
+                    .collect();
+                let record = ProviderRecord::new(Key::from(p.key), provider, addresses);
+                let _ = RecordStore::add_provider(&mut inner, record);
+            }
+        }
+
+        Ok(Self { inner, path })
+    }
+
+    fn persist(&self) {
+        let stored = StoredRecords {
+            records: RecordStore::records(&self.inner)
+                .map(|r| StoredRecord {
+                    key: r.key.to_vec(),
+                    value: r.value.clone(),
+                    publisher: r.publisher.map(|p| p.to_bytes()),
+                })
+                .collect(),
+            providers: RecordStore::provided(&self.inner)
+                .map(|p| StoredProvider {
+                    key: p.key.to_vec(),
+                    provider: p.provider.to_bytes(),
+                    addresses: p.addresses.iter().map(|a| a.to_vec()).collect(),
+                })
+                .collect(),
+        };
+
+        let res = serde_ipld_dagcbor::to_vec(&stored)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+            .and_then(|bytes| fs::write(&self.path, bytes));
+
+        if let Err(e) = res {
+            warn!("failed persisting Kademlia records: {}", e);
+        }
+    }
+}
+
+impl<'a> RecordStore<'a> for FileStore {
+    type RecordsIter = <MemoryStore as RecordStore<'a>>::RecordsIter;
+    type ProvidedIter = <MemoryStore as RecordStore<'a>>::ProvidedIter;
+
+    fn get(&'a self, k: &Key) -> Option<Cow<'_, Record>> {
+        RecordStore::get(&self.inner, k)
+    }
+
+    fn put(&'a mut self, r: Record) -> store::Result<()> {
+        RecordStore::put(&mut self.inner, r)?;
+        self.persist();
+        Ok(())
+    }
+
+    fn remove(&'a mut self, k: &Key) {
+        RecordStore::remove(&mut self.inner, k);
+        self.persist();
+    }
+
+    fn records(&'a self) -> Self::RecordsIter {
+        RecordStore::records(&self.inner)
+    }
+
+    fn add_provider(&'a mut self, record: ProviderRecord) -> store::Result<()> {
+        RecordStore::add_provider(&mut self.inner, record)?;
+        self.persist();
+        Ok(())
+    }
+
+    fn providers(&'a self, key: &Key) -> Vec<ProviderRecord> {
+        RecordStore::providers(&self.inner, key)
+    }
+
+    fn provided(&'a self) -> Self::ProvidedIter {
+        RecordStore::provided(&self.inner)
+    }
+
+    fn remove_provider(&'a mut self, k: &Key, p: &PeerId) {
+        RecordStore::remove_provider(&mut self.inner, k, p);
+        self.persist();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::store::{BoxedStore, FileStore};
+    use libp2p::identity::Keypair;
+    use libp2p::kad::record::store::RecordStore;
+    use libp2p::kad::record::{Key, Record};
+
+    #[test]
+    fn records_survive_restart() {
+        let local_peer_id = Keypair::generate_ed25519().public().to_peer_id();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("kad_records");
+        let key = Key::from(b"room".to_vec());
+
+        {
+            let mut store =
+                BoxedStore::new(Box::new(FileStore::open(local_peer_id, &path).unwrap()));
+            store.put(Record::new(key.clone(), vec![1, 2, 3])).unwrap();
+        }
+
+        let store = BoxedStore::new(Box::new(FileStore::open(local_peer_id, &path).unwrap()));
+
+        assert_eq!(store.get(&key).unwrap().value, vec![1, 2, 3]);
+    }
+}
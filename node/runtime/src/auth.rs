@@ -0,0 +1,72 @@
+use libp2p::PeerId;
+use mpc_p2p::RoomId;
+use std::collections::{HashMap, HashSet};
+
+/// Decides whether a peer is allowed to participate in the sessions of a room.
+pub trait RoomAuthenticator: Send + Sync {
+    /// Returns `true` if `peer_id` may join the sessions of `room_id`.
+    fn authenticate(&self, peer_id: &PeerId, room_id: &RoomId) -> bool;
+}
+
+/// Admits every peer, the default of the [`RuntimeConfig`](crate::RuntimeConfig).
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AllowAll;
+
+impl RoomAuthenticator for AllowAll {
+    fn authenticate(&self, _peer_id: &PeerId, _room_id: &RoomId) -> bool {
+        true
+    }
+}
+
+/// Admits only the peers explicitly allowed into each of the rooms.
+#[derive(Clone, Debug, Default)]
+pub struct AllowList {
+    rooms: HashMap<RoomId, HashSet<PeerId>>,
+}
+
+impl AllowList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allows `peers` to participate in the sessions of `room_id`.
+    pub fn allow(mut self, room_id: RoomId, peers: impl IntoIterator<Item = PeerId>) -> Self {
+        self.rooms.entry(room_id).or_default().extend(peers);
+        self
+    }
+}
+
+impl RoomAuthenticator for AllowList {
+    fn authenticate(&self, peer_id: &PeerId, room_id: &RoomId) -> bool {
+        self.rooms
+            .get(room_id)
+            .map_or(false, |peers| peers.contains(peer_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::auth::{AllowAll, AllowList, RoomAuthenticator};
+    use crate::testing::peer_ids;
+    use mpc_p2p::RoomId;
+
+    #[test]
+    fn allow_all_admits() {
+        let peer_id = peer_ids(1)[0];
+
+        assert!(AllowAll.authenticate(&peer_id, &RoomId::from("tss".to_string())));
+    }
+
+    #[test]
+    fn allow_list_admits_and_rejects() {
+        let peers = peer_ids(2);
+        let (allowed, stranger) = (peers[0], peers[1]);
+        let room_id = RoomId::from("tss".to_string());
+        let other_room_id = RoomId::from("other".to_string());
+        let authenticator = AllowList::new().allow(room_id, [allowed]);
+
+        assert!(authenticator.authenticate(&allowed, &room_id));
+        assert!(!authenticator.authenticate(&stranger, &room_id));
+        assert!(!authenticator.authenticate(&allowed, &other_room_id));
+    }
+}
@@ -0,0 +1,101 @@
+use anyhow::anyhow;
+use futures::{Stream, StreamExt};
+use libp2p::PeerId;
+use mpc_p2p::NetworkEvent;
+use std::collections::HashSet;
+use std::time::Duration;
+
+/// Waits until all `remotes` are connected, starting from the `connected` snapshot and
+/// following the network `events` afterwards.
+///
+/// Fails with an error naming the missing parties if they don't connect within `timeout`.
+pub(crate) async fn wait_for_parties(
+    remotes: Vec<PeerId>,
+    mut connected: HashSet<PeerId>,
+    mut events: impl Stream<Item = NetworkEvent> + Unpin,
+    timeout: Duration,
+) -> anyhow::Result<()> {
+    let missing = |connected: &HashSet<PeerId>| -> Vec<PeerId> {
+        remotes
+            .iter()
+            .filter(|p| !connected.contains(p))
+            .cloned()
+            .collect()
+    };
+
+    let barrier = async {
+        while !missing(&connected).is_empty() {
+            match events.next().await {
+                Some(NetworkEvent::PeerConnected(peer_id)) => {
+                    connected.insert(peer_id);
+                }
+                Some(NetworkEvent::PeerDisconnected(peer_id)) => {
+                    connected.remove(&peer_id);
+                }
+                Some(_) => {}
+                None => return Err(anyhow!("network worker has stopped")),
+            }
+        }
+
+        Ok(())
+    };
+
+    match async_std::future::timeout(timeout, barrier).await {
+        Ok(res) => res,
+        Err(_) => Err(anyhow!(
+            "parties didn't connect within {:?}: {}",
+            timeout,
+            missing(&connected)
+                .iter()
+                .map(|p| p.to_base58())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::barrier::wait_for_parties;
+    use crate::testing::peer_ids;
+    use futures::channel::mpsc;
+    use mpc_p2p::NetworkEvent;
+    use std::time::Duration;
+
+    #[async_std::test]
+    async fn missing_party_times_out() {
+        let remotes = peer_ids(2);
+        let (_events_tx, events) = mpsc::unbounded();
+
+        let err = wait_for_parties(
+            remotes.clone(),
+            [remotes[0]].into_iter().collect(),
+            events,
+            Duration::from_millis(50),
+        )
+        .await
+        .unwrap_err()
+        .to_string();
+
+        assert!(err.contains(&remotes[1].to_base58()));
+        assert!(!err.contains(&remotes[0].to_base58()));
+    }
+
+    #[async_std::test]
+    async fn ready_once_all_connected() {
+        let remotes = peer_ids(2);
+        let (events_tx, events) = mpsc::unbounded();
+        events_tx
+            .unbounded_send(NetworkEvent::PeerConnected(remotes[1]))
+            .unwrap();
+
+        wait_for_parties(
+            remotes.clone(),
+            [remotes[0]].into_iter().collect(),
+            events,
+            Duration::from_secs(5),
+        )
+        .await
+        .unwrap();
+    }
+}
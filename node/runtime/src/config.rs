@@ -0,0 +1,107 @@
+use crate::peerset::DEFAULT_CACHE_TIMEOUT;
+use crate::{AllowAll, RoomAuthenticator};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Settings of the [`RuntimeDaemon`](crate::RuntimeDaemon).
+#[derive(Clone)]
+pub struct RuntimeConfig {
+    /// Time to wait for all parties of the session to connect before computation fails.
+    pub ready_timeout: Duration,
+    /// Number of times a failed point-to-point message is resent, either once its receiver
+    /// reconnects or after the `retransmission_delay`. The sender is notified of the failure
+    /// when all of them fail.
+    pub max_retransmissions: usize,
+    /// Time to wait before resending a failed point-to-point message to a connected receiver.
+    pub retransmission_delay: Duration,
+    /// Limits the rate of the messages accepted from each of the remote parties. Messages above
+    /// the limit wait for it, up to `burst` of them, the rest are refused. Refused point-to-point
+    /// messages are retransmitted by the sender, refused broadcasts are lost. Unlimited if not set.
+    pub incoming_rate_limit: Option<RateLimit>,
+    /// Number of out-of-order messages buffered per sender before the missing ones are skipped.
+    pub sequence_window: usize,
+    /// Time to wait for a missing message before the ones received after it are delivered.
+    pub sequence_gap_timeout: Duration,
+    /// Time the protocol waits for the runtime to read or write the peerset cache.
+    pub cache_timeout: Duration,
+    /// Decides which peers may participate in the sessions of each room, disconnecting the
+    /// rejected ones. Every peer is admitted by default.
+    pub authenticator: Arc<dyn RoomAuthenticator>,
+    /// Number of outgoing messages the protocol can have queued, in flight or waiting to be
+    /// resent before sending blocks, must be greater than zero.
+    pub outgoing_capacity: usize,
+    /// Number of incoming events queued until the protocol reads them. Messages aren't taken
+    /// from the network while the queue is full, so the remote parties' ones are refused and
+    /// resent later, must be greater than zero.
+    pub incoming_capacity: usize,
+}
+
+/// Token bucket rate limit.
+#[derive(Clone, Copy, Debug)]
+pub struct RateLimit {
+    /// Number of messages per second allowed on average.
+    pub messages_per_sec: u32,
+    /// Number of messages allowed to arrive at once.
+    pub burst: u32,
+}
+
+impl RuntimeConfig {
+    /// Checks that the settings can be used by the runtime.
+    pub fn validate(&self) -> crate::Result<()> {
+        if self.outgoing_capacity == 0 {
+            return Err(crate::Error::InvalidConfig(
+                "outgoing capacity must be greater than zero",
+            ));
+        }
+
+        if self.incoming_capacity == 0 {
+            return Err(crate::Error::InvalidConfig(
+                "incoming capacity must be greater than zero",
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for RuntimeConfig {
+    fn default() -> Self {
+        Self {
+            ready_timeout: Duration::from_secs(30),
+            max_retransmissions: 3,
+            retransmission_delay: Duration::from_secs(1),
+            incoming_rate_limit: None,
+            sequence_window: 16,
+            sequence_gap_timeout: Duration::from_secs(5),
+            cache_timeout: DEFAULT_CACHE_TIMEOUT,
+            authenticator: Arc::new(AllowAll),
+            outgoing_capacity: 16,
+            incoming_capacity: 16,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Error, RuntimeConfig};
+
+    #[test]
+    fn zero_outgoing_capacity_rejected() {
+        assert!(RuntimeConfig::default().validate().is_ok());
+
+        let config = RuntimeConfig {
+            outgoing_capacity: 0,
+            ..Default::default()
+        };
+        assert!(matches!(config.validate(), Err(Error::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn zero_incoming_capacity_rejected() {
+        let config = RuntimeConfig {
+            incoming_capacity: 0,
+            ..Default::default()
+        };
+        assert!(matches!(config.validate(), Err(Error::InvalidConfig(_))));
+    }
+}
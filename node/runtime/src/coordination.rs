@@ -1,39 +1,46 @@
 use crate::negotiation::{NegotiationChan, StartMsg};
-use crate::network_proxy::ReceiverProxy;
+use crate::network_proxy::{SessionRouter, SESSION_BUFFER};
 use crate::peerset::Peerset;
-use crate::{ComputeAgentAsync, PeersetMsg};
+use crate::{ComputeAgentAsync, PeersetMsg, RoomAuthenticator};
+use anyhow::anyhow;
 use async_std::stream;
 use async_std::stream::Interval;
 use futures::channel::{mpsc, oneshot};
 use futures::Stream;
 use libp2p::PeerId;
-use log::info;
+use log::warn;
 use mpc_p2p::broadcast::OutgoingResponse;
 use mpc_p2p::{broadcast, MessageType, NetworkService, RoomId};
 use std::future::Future;
 use std::pin::Pin;
+use std::sync::{Arc, Mutex};
 use std::task::{Context, Poll};
 use std::time::Duration;
 
+/// Coordinates the sessions of a room, for as long as it exists: accepts the ones proposed by
+/// the remote parties and starts negotiating the locally requested ones.
 pub(crate) struct Phase1Channel {
     id: RoomId,
-    rx: Option<mpsc::Receiver<broadcast::IncomingMessage>>,
-    on_local_rpc: oneshot::Receiver<LocalRpcMsg>,
+    rx: mpsc::Receiver<broadcast::IncomingMessage>,
+    on_local_rpc: mpsc::Receiver<LocalRpcMsg>,
+    router: Arc<Mutex<SessionRouter>>,
     service: NetworkService,
 }
 
 impl Phase1Channel {
     pub fn new(
         room_id: RoomId,
-        room_rx: mpsc::Receiver<broadcast::IncomingMessage>,
+        coordination_rx: mpsc::Receiver<broadcast::IncomingMessage>,
+        router: Arc<Mutex<SessionRouter>>,
         service: NetworkService,
-    ) -> (Self, oneshot::Sender<LocalRpcMsg>) {
-        let (tx, rx) = oneshot::channel();
+    ) -> (Self, mpsc::Sender<LocalRpcMsg>) {
+        let (tx, rx) = mpsc::channel(1);
         (
             Self {
                 id: room_id,
-                rx: Some(room_rx),
+                rx: coordination_rx,
                 on_local_rpc: rx,
+                router,
                 service,
             },
             tx,
@@ -41,58 +48,97 @@ impl Phase1Channel {
     }
 }
 
-impl Future for Phase1Channel {
-    type Output = Phase1Msg;
+impl Stream for Phase1Channel {
+    type Item = Phase1Msg;
 
-    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        match self.rx.as_mut().unwrap().try_next() {
-            Ok(Some(msg)) => match msg.context.message_type {
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            let msg = match Stream::poll_next(Pin::new(&mut self.rx), cx) {
+                Poll::Ready(Some(msg)) => msg,
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => break,
+            };
+            let session_id = msg.context.session_id;
+
+            match msg.context.message_type {
                 MessageType::Coordination => {
-                    return Poll::Ready(Phase1Msg::FromRemote {
-                        peer_id: msg.peer_id,
-                        session_id: msg.context.session_id,
-                        protocol_id: msg.context.protocol_id,
-                        payload: msg.payload,
-                        response_tx: msg.pending_response,
-                        channel: Phase2Chan {
-                            id: self.id.clone(),
-                            rx: self.rx.take(),
-                            timeout: stream::interval(Duration::from_secs(15)),
-                            service: self.service.clone(),
-                        },
-                    });
+                    // Messages of the session are routed to it from now on, e.g. the start message.
+                    let session_rx = self
+                        .router
+                        .lock()
+                        .unwrap()
+                        .register(session_id, SESSION_BUFFER);
+                    match session_rx {
+                        Some(session_rx) => {
+                            return Poll::Ready(Some(Phase1Msg::FromRemote {
+                                peer_id: msg.peer_id,
+                                session_id,
+                                protocol_id: msg.context.protocol_id,
+                                payload: msg.payload,
+                                response_tx: msg.pending_response,
+                                channel: Phase2Chan {
+                                    id: self.id.clone(),
+                                    rx: Some(session_rx),
+                                    timeout: stream::interval(Duration::from_secs(15)),
+                                    service: self.service.clone(),
+                                },
+                            }));
+                        }
+                        None => {
+                            warn!(
+                                "refusing to join session {session_id} of {}, it is already running",
+                                msg.peer_id.to_base58()
+                            );
+                        }
+                    }
                 }
                 MessageType::Computation => {
-                    panic!("unexpected message type")
+                    warn!(
+                        "dropping message from {}, no active session {session_id}",
+                        msg.peer_id.to_base58()
+                    );
                 }
-            },
-            _ => {}
+            }
         }
 
-        if let Some(LocalRpcMsg {
+        while let Poll::Ready(Some(LocalRpcMsg {
             n,
             args,
             agent,
             on_done,
-        }) = self.on_local_rpc.try_recv().unwrap()
+            authenticator,
+        })) = Stream::poll_next(Pin::new(&mut self.on_local_rpc), cx)
         {
-            return Poll::Ready(Phase1Msg::FromLocal {
-                id: self.id.clone(),
-                n,
-                negotiation: NegotiationChan::new(
-                    self.id.clone(),
-                    self.rx.take().unwrap(),
-                    n,
-                    args,
-                    self.service.clone(),
-                    agent,
-                    on_done,
-                ),
-            });
+            let session_id = agent.session_id();
+            let session_rx = self
+                .router
+                .lock()
+                .unwrap()
+                .register(session_id, SESSION_BUFFER);
+            match session_rx {
+                Some(session_rx) => {
+                    return Poll::Ready(Some(Phase1Msg::FromLocal {
+                        negotiation: NegotiationChan::new(
+                            self.id.clone(),
+                            session_rx,
+                            n,
+                            args,
+                            self.service.clone(),
+                            agent,
+                            on_done,
+                            authenticator,
+                        ),
+                    }));
+                }
+                None => {
+                    let _ = on_done.send(Err(anyhow!(
+                        "session {session_id} is already running in room {}",
+                        self.id.as_str()
+                    )));
+                }
+            }
         }
 
-        // Wake this task to be polled again.
-        cx.waker().wake_by_ref();
         Poll::Pending
     }
 }
@@ -107,12 +153,11 @@ pub(crate) enum Phase1Msg {
         channel: Phase2Chan,                            // listens after we respond
     },
     FromLocal {
-        id: RoomId,
-        n: u16,
         negotiation: NegotiationChan,
     },
 }
 
+/// Waits for the proposer to start the session, listening to the messages of the session only.
 pub(crate) struct Phase2Chan {
     id: RoomId,
     rx: Option<mpsc::Receiver<broadcast::IncomingMessage>>,
@@ -121,9 +166,8 @@ pub(crate) struct Phase2Chan {
 }
 
 impl Phase2Chan {
-    pub fn abort(mut self) -> (RoomId, Phase1Channel, oneshot::Sender<LocalRpcMsg>) {
-        let (ch, tx) = Phase1Channel::new(self.id.clone(), self.rx.take().unwrap(), self.service);
-        return (self.id, ch, tx);
+    pub fn room_id(&self) -> &RoomId {
+        &self.id
     }
 }
 
@@ -137,46 +181,32 @@ impl Future for Phase2Chan {
                     let (start_msg, peerset_rx) =
                         match StartMsg::from_bytes(&*msg.payload, self.service.local_peer_id()) {
                             Ok(res) => res,
-                            Err(_) => {
-                                let (ch, tx) = Phase1Channel::new(
-                                    self.id.clone(),
-                                    self.rx.take().unwrap(),
-                                    self.service.clone(),
-                                );
-                                return Poll::Ready(Phase2Msg::Abort(self.id.clone(), ch, tx));
-                            }
+                            Err(_) => return Poll::Ready(Phase2Msg::Abort),
                         };
                     let parties = start_msg.parties; // todo: check with cache
-                    let (proxy, rx) = ReceiverProxy::new(
-                        self.id.clone(),
-                        self.rx.take().unwrap(),
-                        self.service.clone(),
-                        parties.clone(),
-                    );
                     return Poll::Ready(Phase2Msg::Start {
                         room_id: self.id.clone(),
-                        room_receiver: rx,
-                        receiver_proxy: proxy,
+                        room_receiver: self.rx.take().unwrap(),
                         parties,
                         peerset_rx,
                         init_body: start_msg.body,
+                        response_tx: msg.pending_response,
                     });
                 }
                 MessageType::Computation => {
-                    panic!("unexpected message type")
+                    // Left unacknowledged, so that the sender retransmits it once the session starts.
+                    warn!(
+                        "dropping message from {}, session hasn't started yet",
+                        msg.peer_id.to_base58()
+                    );
                 }
             },
             _ => {}
         }
 
-        // Remote peer gone offline or refused taking in us in set - returning to Phase 1
+        // Remote peer gone offline or refused taking in us in set - the session is dropped.
         if let Poll::Ready(Some(_)) = Stream::poll_next(Pin::new(&mut self.timeout), cx) {
-            let (ch, tx) = Phase1Channel::new(
-                self.id.clone(),
-                self.rx.take().unwrap(),
-                self.service.clone(),
-            );
-            return Poll::Ready(Phase2Msg::Abort(self.id.clone(), ch, tx));
+            return Poll::Ready(Phase2Msg::Abort);
         }
 
         // Wake this task to be polled again.
@@ -189,12 +219,13 @@ pub(crate) enum Phase2Msg {
     Start {
         room_id: RoomId,
         room_receiver: mpsc::Receiver<broadcast::IncomingMessage>,
-        receiver_proxy: ReceiverProxy,
         parties: Peerset,
         peerset_rx: mpsc::Receiver<PeersetMsg>,
         init_body: Vec<u8>,
+        /// Accepts or refuses the start of the session to the proposer.
+        response_tx: oneshot::Sender<OutgoingResponse>,
     },
-    Abort(RoomId, Phase1Channel, oneshot::Sender<LocalRpcMsg>),
+    Abort,
 }
 
 pub(crate) struct LocalRpcMsg {
@@ -202,4 +233,5 @@ pub(crate) struct LocalRpcMsg {
     pub args: Vec<u8>,
     pub agent: Box<dyn ComputeAgentAsync>,
     pub on_done: oneshot::Sender<anyhow::Result<Vec<u8>>>,
+    pub authenticator: Arc<dyn RoomAuthenticator>,
 }
@@ -6,6 +6,12 @@ pub enum Error {
     InconsistentEcho(u16),
     EchoFailed(RequestFailure),
     UnknownProtocol(u64),
+    UnsupportedVersion {
+        protocol_id: u64,
+        requested: u16,
+        supported: u16,
+    },
+    InvalidConfig(&'static str),
     InternalError(anyhow::Error),
 }
 
@@ -22,6 +28,17 @@ impl Display for Error {
             Error::UnknownProtocol(protocol_id) => {
                 write!(f, "unknown protocol with id: {protocol_id}")
             }
+            Error::UnsupportedVersion {
+                protocol_id,
+                requested,
+                supported,
+            } => {
+                write!(
+                    f,
+                    "unsupported version {requested} of protocol {protocol_id}, supported: {supported}"
+                )
+            }
+            Error::InvalidConfig(e) => write!(f, "invalid runtime config: {e}"),
             Error::InternalError(e) => {
                 write!(f, "internal error occurred: {e}")
             }
@@ -1,20 +1,32 @@
+use crate::barrier::wait_for_parties;
 use crate::echo::{EchoMessage, EchoResponse};
-use crate::peerset::Peerset;
-use crate::{ComputeAgentAsync, MessageRouting, PeersetCacher, PeersetMsg, PersistentCacher};
+#[cfg(feature = "metrics")]
+use crate::metrics::RuntimeMetrics;
+use crate::peerset::{PartyIndex, Peerset};
+use crate::rate_limit::{Admission, RateLimiter};
+use crate::retransmit::{PendingMessage, Retransmitter};
+use crate::sequence::{OutgoingSequence, SequenceBuffer, Sequenced};
+use crate::{
+    ComputeAgentAsync, IncomingEvent, MessageRouting, PeersetCacher, PeersetMsg, PersistentCacher,
+    RuntimeConfig,
+};
 use anyhow::anyhow;
-use async_std::task;
 use futures::channel::{mpsc, oneshot};
 use futures::Stream;
-use futures_util::stream::FuturesOrdered;
-use futures_util::{FutureExt, StreamExt};
+use futures_util::stream::{FuturesOrdered, FuturesUnordered};
+use futures_util::{FutureExt, SinkExt, StreamExt};
 use libp2p::PeerId;
-use log::{error, info, warn};
 use mpc_p2p::broadcast::OutgoingResponse;
-use mpc_p2p::{broadcast, MessageContext, MessageType, NetworkService, RoomId};
+use mpc_p2p::{broadcast, MessageContext, MessageType, NetworkEvent, NetworkService, RoomId};
 
+use std::collections::VecDeque;
 use std::future::Future;
 use std::pin::Pin;
+#[cfg(feature = "metrics")]
+use std::sync::Arc;
 use std::task::{Context, Poll};
+use std::time::Instant;
+use tracing::{debug, info_span, warn, Instrument, Span};
 
 pub(crate) struct ProtocolExecution {
     state: Option<ProtocolExecState>,
@@ -29,14 +41,27 @@ struct ProtocolExecState {
     parties: Peerset,
     peerset_rx: mpsc::Receiver<PeersetMsg>,
     from_network: mpsc::Receiver<broadcast::IncomingMessage>,
-    to_protocol: async_channel::Sender<crate::IncomingMessage>,
+    network_events: mpsc::UnboundedReceiver<NetworkEvent>,
+    to_protocol: async_channel::Sender<IncomingEvent>,
     from_protocol: async_channel::Receiver<crate::OutgoingMessage>,
     echo_tx: mpsc::Sender<EchoMessage>,
     agent_future: Pin<Box<dyn Future<Output = anyhow::Result<Vec<u8>>> + Send>>,
     pending_futures: FuturesOrdered<Pin<Box<dyn Future<Output = ()> + Send>>>,
+    broadcast_acks: FuturesUnordered<Pin<Box<dyn Future<Output = ()> + Send>>>,
+    outgoing_seq: OutgoingSequence,
+    incoming_seq: SequenceBuffer<broadcast::IncomingMessage>,
+    to_deliver: VecDeque<IncomingEvent>,
+    incoming_capacity: usize,
+    retransmitter: Retransmitter,
+    outgoing_capacity: usize,
+    rate_limiter: Option<RateLimiter<broadcast::IncomingMessage>>,
     cacher: PersistentCacher,
+    /// Counts the messages of the execution, if set with [`ProtocolExecution::with_metrics`].
+    #[cfg(feature = "metrics")]
+    metrics: Option<Arc<RuntimeMetrics>>,
     on_done: Option<oneshot::Sender<anyhow::Result<Vec<u8>>>>,
-    i: u16,
+    span: Span,
+    i: PartyIndex,
     n: u16,
 }
 
@@ -46,38 +71,78 @@ impl ProtocolExecution {
         args: Vec<u8>,
         agent: Box<dyn ComputeAgentAsync>,
         network_service: NetworkService,
-        parties: Peerset,
+        mut parties: Peerset,
         peerset_rx: mpsc::Receiver<PeersetMsg>,
         cacher: PersistentCacher,
         from_network: mpsc::Receiver<broadcast::IncomingMessage>,
         echo_tx: mpsc::Sender<EchoMessage>,
+        config: &RuntimeConfig,
         on_done: Option<oneshot::Sender<anyhow::Result<Vec<u8>>>>,
     ) -> Self {
         let n = parties.size() as u16;
         let i = parties.index_of(parties.local_peer_id()).unwrap();
         let protocol_id = agent.protocol_id();
+        let session_id = agent.session_id();
+        let span = session_span(session_id, protocol_id, i);
+        parties.set_cache_timeout(config.cache_timeout);
         let (to_protocol, from_runtime) = async_channel::bounded((n - 1) as usize);
-        let (to_runtime, from_protocol) = async_channel::bounded((n - 1) as usize);
+        let (to_runtime, from_protocol) = async_channel::bounded(config.outgoing_capacity);
 
-        let agent_future = agent.compute(parties.clone(), args, from_runtime, to_runtime);
+        let network_events = network_service.event_stream();
+
+        // Computation starts once all parties are connected, so that early messages aren't lost.
+        let agent_future = {
+            let service = network_service.clone();
+            let parties = parties.clone();
+            let remotes = parties.remotes().cloned().collect();
+            let ready_timeout = config.ready_timeout;
+
+            async move {
+                let events = service.event_stream();
+                let connected = service.connected_peers().await;
+                wait_for_parties(remotes, connected, events, ready_timeout).await?;
+
+                agent.compute(parties, args, from_runtime, to_runtime).await
+            }
+            .instrument(span.clone())
+            .boxed()
+        };
 
         Self {
             state: Some(ProtocolExecState {
                 room_id,
                 local_peer_id: network_service.local_peer_id(),
                 protocol_id,
-                session_id: 0,
+                session_id,
                 network_service,
                 parties,
                 peerset_rx,
                 from_network,
+                network_events,
                 to_protocol,
                 from_protocol,
                 echo_tx,
                 agent_future,
                 pending_futures: FuturesOrdered::new(),
+                broadcast_acks: FuturesUnordered::new(),
+                outgoing_seq: OutgoingSequence::default(),
+                incoming_seq: SequenceBuffer::new(
+                    config.sequence_window,
+                    config.sequence_gap_timeout,
+                ),
+                to_deliver: VecDeque::new(),
+                incoming_capacity: config.incoming_capacity,
+                retransmitter: Retransmitter::new(
+                    config.max_retransmissions,
+                    config.retransmission_delay,
+                ),
+                outgoing_capacity: config.outgoing_capacity,
+                rate_limiter: config.incoming_rate_limit.map(RateLimiter::new),
                 cacher,
+                #[cfg(feature = "metrics")]
+                metrics: None,
                 on_done,
+                span,
                 i,
                 n,
             }),
@@ -85,6 +150,17 @@ impl ProtocolExecution {
     }
 }
 
+impl ProtocolExecution {
+    /// Counts the messages sent and received by this execution in the runtime `metrics`.
+    #[cfg(feature = "metrics")]
+    pub fn with_metrics(mut self, metrics: Arc<RuntimeMetrics>) -> Self {
+        if let Some(state) = self.state.as_mut() {
+            state.metrics = Some(metrics);
+        }
+        self
+    }
+}
+
 impl Future for ProtocolExecution {
     type Output = crate::Result<()>;
 
@@ -98,16 +174,29 @@ impl Future for ProtocolExecution {
             parties,
             peerset_rx: mut from_peerset,
             mut from_network,
+            mut network_events,
             to_protocol,
             mut from_protocol,
             mut echo_tx,
             mut agent_future,
             mut pending_futures,
+            mut broadcast_acks,
+            mut outgoing_seq,
+            mut incoming_seq,
+            mut to_deliver,
+            incoming_capacity,
+            mut retransmitter,
+            outgoing_capacity,
+            mut rate_limiter,
             mut cacher,
+            #[cfg(feature = "metrics")]
+            metrics,
             on_done,
+            span,
             i,
             n,
         } = self.state.take().unwrap();
+        let _entered = span.clone().entered();
 
         if let Poll::Ready(Some(message)) = Stream::poll_next(Pin::new(&mut from_peerset), cx) {
             match message {
@@ -120,54 +209,59 @@ impl Future for ProtocolExecution {
             }
         }
 
-        if let Poll::Ready(Some(message)) = Stream::poll_next(Pin::new(&mut from_protocol), cx) {
-            info!("outgoing message to {:?}", message.to);
+        // Parked messages count too, so that a failing peer can't grow them without bound.
+        let in_flight = retransmitter.in_flight() + retransmitter.parked() + broadcast_acks.len();
+        if let Poll::Ready(Some(message)) = poll_outgoing(
+            &mut from_protocol,
+            session_id,
+            in_flight,
+            outgoing_capacity,
+            cx,
+        ) {
+            debug!(to = ?message.to, "outgoing message");
+            #[cfg(feature = "metrics")]
+            if let Some(metrics) = metrics.as_ref() {
+                metrics.message_sent();
+            }
 
             match message.to {
                 MessageRouting::PointToPoint(remote_index) => {
-                    let (res_tx, mut res_rx) = mpsc::channel(1);
-
-                    pending_futures.push(
-                        network_service
-                            .clone()
-                            .send_message_owned(
-                                room_id.clone(),
-                                parties[remote_index - 1],
-                                MessageContext {
-                                    message_type: MessageType::Computation,
-                                    session_id,
-                                    protocol_id,
-                                },
-                                message.body,
-                                res_tx,
-                            )
-                            .boxed(),
+                    send_direct(
+                        &network_service,
+                        &room_id,
+                        PendingMessage {
+                            peer_id: parties[remote_index],
+                            context: MessageContext {
+                                message_type: MessageType::Computation,
+                                session_id,
+                                protocol_id,
+                                seq: outgoing_seq.next(message.to),
+                            },
+                            body: message.body,
+                            sent: message.sent,
+                            retries: 0,
+                        },
+                        &mut pending_futures,
+                        &mut retransmitter,
                     );
-
-                    // todo: handle in same Future::poll
-                    task::spawn(async move {
-                        if let Err(e) = res_rx.select_next_some().await {
-                            error!("party responded with error: {e}");
-                        }
-                    });
-
-                    if let Some(tx) = message.sent {
-                        let _ = tx.send(());
-                    }
                 }
                 MessageRouting::Broadcast => {
                     let (res_tx, res_rx) = mpsc::channel((n - 1) as usize);
+                    let (echo_res_tx, echo_res_rx) = mpsc::channel((n - 1) as usize);
 
                     pending_futures.push(
                         network_service
                             .clone()
                             .multicast_message_owned(
                                 room_id.clone(),
-                                parties.clone().remotes_iter(),
+                                parties.remotes().cloned().collect::<Vec<_>>().into_iter(),
+                                // Sessions are proposed with coordination messages, so broadcasts
+                                // arriving after the session is over must not be taken for one.
                                 MessageContext {
-                                    message_type: MessageType::Coordination,
+                                    message_type: MessageType::Computation,
                                     session_id,
                                     protocol_id,
+                                    seq: outgoing_seq.next(message.to),
                                 },
                                 message.body.clone(),
                                 Some(res_tx),
@@ -175,11 +269,16 @@ impl Future for ProtocolExecution {
                             .boxed(),
                     );
 
+                    broadcast_acks.push(
+                        forward_broadcast_acks(res_rx, echo_res_tx, (n - 1) as usize, message.sent)
+                            .boxed(),
+                    );
+
                     echo_tx
                         .try_send(EchoMessage {
-                            sender: i + 1,
+                            sender: u16::from(i) + 1,
                             payload: message.body,
-                            response: EchoResponse::Outgoing(res_rx),
+                            response: EchoResponse::Outgoing(echo_res_rx),
                         })
                         .expect("echo channel is expected to be open");
                 }
@@ -194,39 +293,153 @@ impl Future for ProtocolExecution {
             }
         }
 
-        if let Poll::Ready(Some(message)) = Stream::poll_next(Pin::new(&mut from_network), cx) {
-            info!("incoming message from {}", message.peer_id.to_base58());
-
-            if message.is_broadcast {
-                echo_tx
-                    .try_send(EchoMessage {
-                        sender: message.peer_index + 1,
-                        payload: message.payload.clone(),
-                        response: EchoResponse::Incoming(message.pending_response),
-                    })
-                    .expect("echo channel is expected to be open");
-            } else {
-                if let Err(_) = message.pending_response.send(OutgoingResponse {
-                    result: Ok(vec![]),
-                    sent_feedback: None,
-                }) {
-                    warn!("failed sending acknowledgement to remote");
+        while let Poll::Ready(Some(())) = broadcast_acks.poll_next_unpin(cx) {}
+
+        retransmitter.poll_responses(cx);
+
+        let mut admitted = vec![];
+        // Messages are left to the network while the protocol is behind on reading, so that their
+        // senders are held back rather than the queued events growing without bound.
+        let polled = if to_deliver.len() < incoming_capacity {
+            Stream::poll_next(Pin::new(&mut from_network), cx)
+        } else {
+            Poll::Pending
+        };
+        if let Poll::Ready(Some(mut message)) = polled {
+            debug!(peer_id = %message.peer_id.to_base58(), "incoming message");
+
+            match parties.index_of(&message.peer_id) {
+                None => {
+                    // Messages are routed to the session by its id only, whoever sent them.
+                    warn!(
+                        peer_id = %message.peer_id.to_base58(),
+                        "dropping message from a peer outside of the session"
+                    );
+                }
+                Some(index) => {
+                    message.peer_index = index.into();
+                    let peer_id = message.peer_id;
+                    let admission = match rate_limiter.as_mut() {
+                        Some(limiter) => limiter.admit(&peer_id, message, Instant::now()),
+                        None => Admission::Allowed(message),
+                    };
+                    match admission {
+                        Admission::Allowed(message) => admitted.push(message),
+                        Admission::Delayed => {
+                            debug!(party_index = %index, "delaying message, rate limit exceeded");
+                        }
+                        Admission::Refused(message) => {
+                            // Refused point-to-point message keeps its sequence number unused, so
+                            // the sender's retransmission fills the gap. Broadcasts aren't
+                            // retransmitted, so the refused ones are lost.
+                            warn!(
+                                party_index = %index,
+                                peer_id = %message.peer_id.to_base58(),
+                                broadcast = message.is_broadcast,
+                                "refusing message, rate limit exceeded"
+                            );
+                            if let Err(_) = message.pending_response.send(OutgoingResponse {
+                                result: Err(()),
+                                sent_feedback: None,
+                            }) {
+                                warn!("failed sending refusal to remote");
+                            }
+                        }
+                    }
                 }
             }
+        }
 
-            to_protocol
-                .try_send(crate::IncomingMessage {
-                    from: message.peer_index + 1,
-                    to: if message.is_broadcast {
-                        MessageRouting::Broadcast
-                    } else {
-                        MessageRouting::PointToPoint(i + 1)
-                    },
-                    body: message.payload,
-                })
-                .expect("application channel is expected to be open");
+        // Delayed messages are admitted as the tokens of their senders are refilled.
+        if let Some(limiter) = rate_limiter.as_mut() {
+            admitted.extend(limiter.take_ready(Instant::now()));
+        }
+
+        for message in admitted {
+            match incoming_seq.push(
+                message.peer_index.into(),
+                message.is_broadcast,
+                message.context.seq,
+                message,
+            ) {
+                Sequenced::Ready(messages) => {
+                    for message in messages {
+                        #[cfg(feature = "metrics")]
+                        if let Some(metrics) = metrics.as_ref() {
+                            metrics.message_received();
+                        }
+                        deliver_incoming(message, i, &mut echo_tx, &mut to_deliver);
+                    }
+                }
+                Sequenced::Duplicate(message) => {
+                    warn!(
+                        seq = message.context.seq,
+                        peer_id = %message.peer_id.to_base58(),
+                        "dropping duplicate message"
+                    );
+                    if let Err(_) = message.pending_response.send(OutgoingResponse {
+                        result: Ok(vec![]),
+                        sent_feedback: None,
+                    }) {
+                        warn!("failed sending acknowledgement to remote");
+                    }
+                }
+            }
         }
 
+        // Messages missing for too long are unlikely to arrive, so the ones after them are delivered.
+        for message in incoming_seq.flush_expired(Instant::now()) {
+            #[cfg(feature = "metrics")]
+            if let Some(metrics) = metrics.as_ref() {
+                metrics.message_received();
+            }
+            deliver_incoming(message, i, &mut echo_tx, &mut to_deliver);
+        }
+
+        while let Poll::Ready(Some(event)) = Stream::poll_next(Pin::new(&mut network_events), cx) {
+            match event {
+                NetworkEvent::PeerConnected(peer_id) => {
+                    for message in retransmitter.take_parked(&peer_id) {
+                        send_direct(
+                            &network_service,
+                            &room_id,
+                            message,
+                            &mut pending_futures,
+                            &mut retransmitter,
+                        );
+                    }
+                }
+                NetworkEvent::PeerDisconnected(peer_id) => {
+                    retransmitter.peer_disconnected(peer_id);
+                    if let Some(index) = party_left(&parties, &peer_id) {
+                        warn!(
+                            party_index = %index,
+                            peer_id = %peer_id.to_base58(),
+                            "party left the session"
+                        );
+                        to_deliver.push_back(IncomingEvent::PartyLeft(index));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        // Failed deliveries are retried even if the remote never disconnects, but not while it is
+        // disconnected.
+        for message in retransmitter.take_due(Instant::now()) {
+            send_direct(
+                &network_service,
+                &room_id,
+                message,
+                &mut pending_futures,
+                &mut retransmitter,
+            );
+        }
+
+        // Events are kept in order until the protocol catches up on reading them, rather than
+        // being dropped, since the remote parties already got their messages acknowledged.
+        flush_to_protocol(&mut to_deliver, &to_protocol);
+
         match Future::poll(Pin::new(&mut agent_future), cx) {
             Poll::Ready(Ok(res)) => {
                 if let Some(tx) = on_done {
@@ -251,13 +464,25 @@ impl Future for ProtocolExecution {
                     parties,
                     peerset_rx: from_peerset,
                     from_network,
+                    network_events,
                     to_protocol,
                     from_protocol,
                     echo_tx,
                     agent_future,
                     pending_futures,
+                    broadcast_acks,
+                    outgoing_seq,
+                    incoming_seq,
+                    to_deliver,
+                    incoming_capacity,
+                    retransmitter,
+                    outgoing_capacity,
+                    rate_limiter,
                     cacher,
+                    #[cfg(feature = "metrics")]
+                    metrics,
                     on_done,
+                    span,
                     i,
                     n,
                 });
@@ -269,3 +494,745 @@ impl Future for ProtocolExecution {
         }
     }
 }
+
+/// Span that correlates the events of the session, entered while it is being executed.
+fn session_span(session_id: u64, protocol_id: u64, i: PartyIndex) -> Span {
+    info_span!(
+        "session",
+        session_id,
+        protocol_id,
+        party_index = u16::from(i)
+    )
+}
+
+/// Takes the next message of the session from the protocol, unless `in_flight` messages have
+/// reached the `capacity`. The protocol is then left waiting on the full channel until some are
+/// delivered. Messages tagged with another session are dropped.
+fn poll_outgoing(
+    from_protocol: &mut async_channel::Receiver<crate::OutgoingMessage>,
+    session_id: u64,
+    in_flight: usize,
+    capacity: usize,
+    cx: &mut Context<'_>,
+) -> Poll<Option<crate::OutgoingMessage>> {
+    if in_flight >= capacity {
+        return Poll::Pending;
+    }
+
+    loop {
+        match Stream::poll_next(Pin::new(&mut *from_protocol), cx) {
+            Poll::Ready(Some(message)) if message.session_id != session_id => {
+                // Dropping `sent` lets the protocol observe the failure.
+                warn!(
+                    "dropping outgoing message of session {}, expected session {session_id}",
+                    message.session_id
+                );
+            }
+            poll => return poll,
+        }
+    }
+}
+
+/// Sends point-to-point `message` and hands it to the `retransmitter` until it is delivered.
+fn send_direct(
+    network_service: &NetworkService,
+    room_id: &RoomId,
+    message: PendingMessage,
+    pending_futures: &mut FuturesOrdered<Pin<Box<dyn Future<Output = ()> + Send>>>,
+    retransmitter: &mut Retransmitter,
+) {
+    let (res_tx, res_rx) = mpsc::channel(1);
+
+    pending_futures.push(
+        network_service
+            .clone()
+            .send_message_owned(
+                room_id.clone(),
+                message.peer_id,
+                message.context,
+                message.body.clone(),
+                res_tx,
+            )
+            .boxed(),
+    );
+
+    retransmitter.track(message, res_rx);
+}
+
+/// Passes a message received from the network to the echo gadget and queues it for the protocol.
+fn deliver_incoming(
+    message: broadcast::IncomingMessage,
+    i: PartyIndex,
+    echo_tx: &mut mpsc::Sender<EchoMessage>,
+    to_deliver: &mut VecDeque<IncomingEvent>,
+) {
+    if message.is_broadcast {
+        echo_tx
+            .try_send(EchoMessage {
+                sender: message.peer_index + 1,
+                payload: message.payload.clone(),
+                response: EchoResponse::Incoming(message.pending_response),
+            })
+            .expect("echo channel is expected to be open");
+    } else {
+        if let Err(_) = message.pending_response.send(OutgoingResponse {
+            result: Ok(vec![]),
+            sent_feedback: None,
+        }) {
+            warn!("failed sending acknowledgement to remote");
+        }
+    }
+
+    to_deliver.push_back(IncomingEvent::Message(crate::IncomingMessage {
+        session_id: message.context.session_id,
+        from: message.peer_index.into(),
+        to: if message.is_broadcast {
+            MessageRouting::Broadcast
+        } else {
+            MessageRouting::PointToPoint(i)
+        },
+        body: message.payload,
+        seq: message.context.seq,
+    }));
+}
+
+/// Returns the index of the remote session party with the given `peer_id`.
+fn party_left(parties: &Peerset, peer_id: &PeerId) -> Option<PartyIndex> {
+    if peer_id == parties.local_peer_id() {
+        return None;
+    }
+
+    parties.index_of(peer_id)
+}
+
+/// Passes the queued events to the protocol in order, keeping the ones that couldn't be
+/// delivered yet due to the channel being full.
+fn flush_to_protocol(
+    to_deliver: &mut VecDeque<IncomingEvent>,
+    to_protocol: &async_channel::Sender<IncomingEvent>,
+) {
+    while let Some(event) = to_deliver.pop_front() {
+        match to_protocol.try_send(event) {
+            Ok(()) => {}
+            Err(async_channel::TrySendError::Full(event)) => {
+                to_deliver.push_front(event);
+                break;
+            }
+            Err(async_channel::TrySendError::Closed(_)) => {
+                // Protocol is no longer interested in the incoming events.
+                to_deliver.clear();
+            }
+        }
+    }
+}
+
+/// Relays responses of the remote parties to a broadcast message into the echo gadget and
+/// notifies `sent` once all `num_remotes` parties have received the message.
+///
+/// If delivery to any of the parties fails, `sent` is dropped instead, so the awaiting side
+/// observes cancellation rather than a successful broadcast.
+async fn forward_broadcast_acks(
+    mut responses: mpsc::Receiver<Result<(PeerId, Vec<u8>), broadcast::RequestFailure>>,
+    mut to_echo: mpsc::Sender<Result<(PeerId, Vec<u8>), broadcast::RequestFailure>>,
+    num_remotes: usize,
+    sent: Option<oneshot::Sender<()>>,
+) {
+    let mut delivered = 0;
+
+    for _ in 0..num_remotes {
+        let response = match responses.next().await {
+            Some(response) => response,
+            None => break,
+        };
+
+        match &response {
+            Ok(_) => delivered += 1,
+            Err(e) => warn!("broadcast delivery failed: {e}"),
+        }
+
+        if to_echo.send(response).await.is_err() {
+            warn!("echo gadget is no longer interested in broadcast responses");
+        }
+    }
+
+    if delivered == num_remotes {
+        if let Some(tx) = sent {
+            let _ = tx.send(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::execution::{forward_broadcast_acks, party_left, poll_outgoing};
+    use crate::peerset::Peerset;
+    use crate::testing::{
+        computation_context, execute, peer_ids, spawn_node, spawn_node_with_key, wait_connected,
+        TestAgent,
+    };
+    use crate::{IncomingEvent, MessageRouting, OutgoingMessage, RuntimeConfig};
+    use futures::channel::{mpsc, oneshot};
+    use futures_util::{FutureExt, StreamExt};
+    use libp2p::identity::{ed25519, PublicKey};
+    use mpc_p2p::broadcast::{self, OutgoingResponse, RequestFailure};
+    use mpc_p2p::{MessageType, NodeKeyConfig, Secret};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::task::{Context, Poll};
+    use std::time::Duration;
+    use tracing::info;
+    use tracing_test::traced_test;
+
+    fn three_party_peerset() -> Peerset {
+        let peer_ids = peer_ids(3);
+        let local_peer_id = peer_ids[0];
+        let (peerset, _) = Peerset::new(peer_ids.into_iter(), local_peer_id);
+        peerset
+    }
+
+    #[async_std::test]
+    async fn broadcast_sent_after_all_remotes() {
+        let remotes: Vec<_> = three_party_peerset().remotes_iter().collect();
+        let (mut res_tx, res_rx) = mpsc::channel(remotes.len());
+        let (echo_tx, echo_rx) = mpsc::channel(remotes.len());
+        let (sent_tx, mut sent_rx) = oneshot::channel();
+
+        res_tx.try_send(Ok((remotes[0], vec![]))).unwrap();
+        let forwarding = async_std::task::spawn(forward_broadcast_acks(
+            res_rx,
+            echo_tx,
+            remotes.len(),
+            Some(sent_tx),
+        ));
+
+        async_std::task::yield_now().await;
+        assert_eq!(sent_rx.try_recv(), Ok(None));
+
+        res_tx.try_send(Ok((remotes[1], vec![]))).unwrap();
+        forwarding.await;
+
+        assert_eq!(sent_rx.try_recv(), Ok(Some(())));
+        assert_eq!(echo_rx.collect::<Vec<_>>().await.len(), remotes.len());
+    }
+
+    #[async_std::test]
+    async fn broadcast_sent_dropped_on_failure() {
+        let remotes: Vec<_> = three_party_peerset().remotes_iter().collect();
+        let (mut res_tx, res_rx) = mpsc::channel(remotes.len());
+        let (echo_tx, _echo_rx) = mpsc::channel(remotes.len());
+        let (sent_tx, sent_rx) = oneshot::channel();
+
+        res_tx.try_send(Ok((remotes[0], vec![]))).unwrap();
+        res_tx.try_send(Err(RequestFailure::NotConnected)).unwrap();
+        forward_broadcast_acks(res_rx, echo_tx, remotes.len(), Some(sent_tx)).await;
+
+        assert!(sent_rx.await.is_err());
+    }
+
+    #[test]
+    fn local_party_never_left() {
+        let parties = three_party_peerset();
+        let remote = parties.clone().remotes_iter().next().unwrap();
+
+        assert_eq!(party_left(&parties, parties.local_peer_id()), None);
+        assert_eq!(party_left(&parties, &remote), parties.index_of(&remote));
+    }
+
+    #[async_std::test]
+    async fn disconnected_party_reaches_agent() {
+        let remote = spawn_node("party-left", vec![]).await;
+        let local = spawn_node("party-left", vec![remote.address.clone()]).await;
+        let remote_peer_id = remote.address.peer_id;
+        let local_service = local.service.clone();
+        let parties = vec![local_service.local_peer_id(), remote_peer_id];
+        wait_connected(&local_service, remote_peer_id).await;
+
+        let (started_tx, started_rx) = oneshot::channel();
+        let agent = TestAgent {
+            session_id: 0,
+            protocol_id: 0,
+            compute: Box::new(move |parties, incoming, _outgoing| {
+                async move {
+                    let _ = started_tx.send(());
+                    loop {
+                        if let IncomingEvent::PartyLeft(index) = incoming.recv().await? {
+                            return Ok(parties[index].to_bytes());
+                        }
+                    }
+                }
+                .boxed()
+            }),
+        };
+        let result = execute(local, parties, agent, &RuntimeConfig::default());
+
+        started_rx.await.unwrap();
+        local_service.disconnect_peer(remote_peer_id).await;
+
+        let left = async_std::future::timeout(Duration::from_secs(10), result)
+            .await
+            .expect("party left wasn't reported")
+            .unwrap()
+            .unwrap();
+        assert_eq!(left, remote_peer_id.to_bytes());
+    }
+
+    #[async_std::test]
+    async fn resent_once_party_reconnects() {
+        let mut remote = spawn_node("reconnect", vec![]).await;
+        let local = spawn_node("reconnect", vec![remote.address.clone()]).await;
+        let local_peer_id = local.service.local_peer_id();
+        let remote_peer_id = remote.address.peer_id;
+        let local_service = local.service.clone();
+        let parties = vec![local_peer_id, remote_peer_id];
+        wait_connected(&local_service, remote_peer_id).await;
+
+        let agent = TestAgent {
+            session_id: 0,
+            protocol_id: 0,
+            compute: Box::new(move |parties, _incoming, outgoing| {
+                async move {
+                    let remote_index = parties.index_of(parties.remotes().next().unwrap()).unwrap();
+                    let (sent_tx, sent_rx) = oneshot::channel();
+                    outgoing
+                        .send(OutgoingMessage {
+                            session_id: 0,
+                            body: vec![1],
+                            to: MessageRouting::PointToPoint(remote_index),
+                            sent: Some(sent_tx),
+                        })
+                        .await?;
+                    sent_rx.await?;
+                    Ok(vec![])
+                }
+                .boxed()
+            }),
+        };
+        let config = RuntimeConfig {
+            retransmission_delay: Duration::from_millis(100),
+            ..Default::default()
+        };
+        let result = execute(local, parties, agent, &config);
+
+        // Remote disconnects before acknowledging the first attempt.
+        let message = remote.room_rx.next().await.unwrap();
+        assert_eq!(message.payload, vec![1]);
+        local_service.disconnect_peer(remote_peer_id).await;
+        drop(message);
+
+        // Message isn't resent while the remote is disconnected, however long it takes.
+        async_std::task::sleep(Duration::from_secs(1)).await;
+        assert!(remote.room_rx.try_next().is_err());
+
+        // Remote reconnects by sending a message of another session.
+        let (res_tx, _res_rx) = mpsc::channel(1);
+        remote
+            .service
+            .send_message(
+                &remote.room_id,
+                local_peer_id,
+                computation_context(1, 0),
+                vec![],
+                res_tx,
+            )
+            .await;
+
+        let message = async_std::future::timeout(Duration::from_secs(10), remote.room_rx.next())
+            .await
+            .expect("message wasn't resent")
+            .unwrap();
+        assert_eq!(message.payload, vec![1]);
+        assert_eq!(message.context.seq, 0);
+        message
+            .pending_response
+            .send(OutgoingResponse {
+                result: Ok(vec![]),
+                sent_feedback: None,
+            })
+            .unwrap();
+
+        async_std::future::timeout(Duration::from_secs(10), result)
+            .await
+            .expect("computation didn't finish")
+            .unwrap()
+            .unwrap();
+    }
+
+    #[async_std::test]
+    async fn slow_protocol_receives_all_messages() {
+        let num_messages = 5;
+        let remote = spawn_node("slow-protocol", vec![]).await;
+        let local = spawn_node("slow-protocol", vec![remote.address.clone()]).await;
+        let local_peer_id = local.service.local_peer_id();
+        let parties = vec![local_peer_id, remote.address.peer_id];
+        wait_connected(&local.service, remote.address.peer_id).await;
+
+        let agent = TestAgent {
+            session_id: 0,
+            protocol_id: 0,
+            compute: Box::new(move |_parties, incoming, _outgoing| {
+                async move {
+                    // Lets the messages arrive faster than they are read.
+                    async_std::task::sleep(Duration::from_millis(500)).await;
+                    let mut received = vec![];
+                    while received.len() < num_messages {
+                        if let IncomingEvent::Message(message) = incoming.recv().await? {
+                            received.push(message.seq as u8);
+                        }
+                    }
+                    Ok(received)
+                }
+                .boxed()
+            }),
+        };
+        let result = execute(local, parties, agent, &RuntimeConfig::default());
+
+        let (res_tx, mut res_rx) = mpsc::channel(num_messages);
+        for seq in 0..num_messages {
+            remote
+                .service
+                .send_message(
+                    &remote.room_id,
+                    local_peer_id,
+                    computation_context(0, seq as u64),
+                    vec![],
+                    res_tx.clone(),
+                )
+                .await;
+        }
+        for _ in 0..num_messages {
+            assert!(res_rx.next().await.unwrap().is_ok());
+        }
+
+        let received = async_std::future::timeout(Duration::from_secs(10), result)
+            .await
+            .expect("messages weren't received")
+            .unwrap()
+            .unwrap();
+        assert_eq!(received, (0..num_messages as u8).collect::<Vec<_>>());
+    }
+
+    #[async_std::test]
+    async fn late_party_delivers_all_messages() {
+        let num_messages = 20;
+        let remote_key = ed25519::Keypair::generate();
+        let remote_peer_id = PublicKey::Ed25519(remote_key.public()).to_peer_id();
+        let local = spawn_node("late-party", vec![]).await;
+        let local_address = local.address.clone();
+        let parties = vec![local_address.peer_id, remote_peer_id];
+
+        // Local protocol is behind on reading, while its queue holds few of the events.
+        let agent = TestAgent {
+            session_id: 0,
+            protocol_id: 0,
+            compute: Box::new(move |_parties, incoming, _outgoing| {
+                async move {
+                    async_std::task::sleep(Duration::from_millis(500)).await;
+                    let mut received = vec![];
+                    while received.len() < num_messages {
+                        if let IncomingEvent::Message(message) = incoming.recv().await? {
+                            received.push(message.seq);
+                        }
+                    }
+                    Ok(received.into_iter().map(|seq| seq as u8).collect())
+                }
+                .boxed()
+            }),
+        };
+        let local_config = RuntimeConfig {
+            incoming_capacity: 4,
+            ..Default::default()
+        };
+        let result = execute(local, parties.clone(), agent, &local_config);
+
+        // Remote party connects once the local one is already waiting for it.
+        async_std::task::sleep(Duration::from_secs(1)).await;
+        let remote = spawn_node_with_key(
+            "late-party",
+            vec![local_address],
+            NodeKeyConfig::Ed25519(Secret::Input(remote_key.secret())),
+        )
+        .await;
+        assert_eq!(remote.address.peer_id, remote_peer_id);
+        let agent = TestAgent {
+            session_id: 0,
+            protocol_id: 0,
+            compute: Box::new(move |parties, _incoming, outgoing| {
+                async move {
+                    let local = parties.index_of(parties.remotes().next().unwrap()).unwrap();
+                    let mut sent = vec![];
+                    for _ in 0..num_messages {
+                        let (sent_tx, sent_rx) = oneshot::channel();
+                        outgoing
+                            .send(OutgoingMessage {
+                                session_id: 0,
+                                body: vec![],
+                                to: MessageRouting::PointToPoint(local),
+                                sent: Some(sent_tx),
+                            })
+                            .await?;
+                        sent.push(sent_rx);
+                    }
+                    for sent_rx in sent {
+                        sent_rx.await?;
+                    }
+                    Ok(vec![])
+                }
+                .boxed()
+            }),
+        };
+        let remote_config = RuntimeConfig {
+            max_retransmissions: 10,
+            retransmission_delay: Duration::from_millis(200),
+            ..Default::default()
+        };
+        let sent = execute(remote, parties, agent, &remote_config);
+
+        let received = async_std::future::timeout(Duration::from_secs(20), result)
+            .await
+            .expect("messages weren't received")
+            .unwrap()
+            .unwrap();
+        assert_eq!(received, (0..num_messages as u8).collect::<Vec<_>>());
+        assert!(sent.await.unwrap().is_ok());
+    }
+
+    #[async_std::test]
+    async fn stalled_peer_blocks_protocol() {
+        let capacity = 2;
+        let num_messages = 10;
+        let mut remote = spawn_node("stalled-peer", vec![]).await;
+        let local = spawn_node("stalled-peer", vec![remote.address.clone()]).await;
+        let parties = vec![local.service.local_peer_id(), remote.address.peer_id];
+        wait_connected(&local.service, remote.address.peer_id).await;
+
+        let produced = Arc::new(AtomicUsize::new(0));
+        let agent = {
+            let produced = produced.clone();
+            TestAgent {
+                session_id: 0,
+                protocol_id: 0,
+                compute: Box::new(move |parties, _incoming, outgoing| {
+                    async move {
+                        let remote = parties.index_of(parties.remotes().next().unwrap()).unwrap();
+                        let mut sent = vec![];
+                        for _ in 0..num_messages {
+                            let (sent_tx, sent_rx) = oneshot::channel();
+                            outgoing
+                                .send(OutgoingMessage {
+                                    session_id: 0,
+                                    body: vec![0; 1024],
+                                    to: MessageRouting::PointToPoint(remote),
+                                    sent: Some(sent_tx),
+                                })
+                                .await?;
+                            produced.fetch_add(1, Ordering::SeqCst);
+                            sent.push(sent_rx);
+                        }
+                        for sent_rx in sent {
+                            sent_rx.await?;
+                        }
+                        Ok(vec![])
+                    }
+                    .boxed()
+                }),
+            }
+        };
+        let config = RuntimeConfig {
+            outgoing_capacity: capacity,
+            ..Default::default()
+        };
+        let result = execute(local, parties, agent, &config);
+
+        // Remote takes the messages in, but never acknowledges them.
+        let mut stalled = vec![];
+        for _ in 0..capacity {
+            stalled.push(remote.room_rx.next().await.unwrap());
+        }
+        async_std::task::sleep(Duration::from_secs(1)).await;
+        assert!(remote.room_rx.try_next().is_err());
+        assert_eq!(produced.load(Ordering::SeqCst), 2 * capacity);
+
+        // Protocol is released once the remote catches up.
+        let ack = |message: broadcast::IncomingMessage| {
+            message
+                .pending_response
+                .send(OutgoingResponse {
+                    result: Ok(vec![]),
+                    sent_feedback: None,
+                })
+                .unwrap()
+        };
+        stalled.into_iter().for_each(ack);
+        async_std::task::spawn(async move {
+            while let Some(message) = remote.room_rx.next().await {
+                ack(message);
+            }
+        });
+
+        async_std::future::timeout(Duration::from_secs(10), result)
+            .await
+            .expect("protocol wasn't released")
+            .unwrap()
+            .unwrap();
+        assert_eq!(produced.load(Ordering::SeqCst), num_messages);
+    }
+
+    #[async_std::test]
+    async fn slow_transport_blocks_protocol() {
+        let capacity = 2;
+        let (to_runtime, mut from_protocol) = async_channel::bounded(capacity);
+        let produced = Arc::new(AtomicUsize::new(0));
+
+        let producer = {
+            let produced = produced.clone();
+            async_std::task::spawn(async move {
+                for _ in 0..10 {
+                    let message = OutgoingMessage {
+                        session_id: 0,
+                        body: vec![0; 1024],
+                        to: MessageRouting::Broadcast,
+                        sent: None,
+                    };
+                    if to_runtime.send(message).await.is_err() {
+                        break;
+                    }
+                    produced.fetch_add(1, Ordering::SeqCst);
+                }
+            })
+        };
+
+        // Transport never acknowledges the messages, so they all stay in flight.
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let mut in_flight = 0;
+        for _ in 0..10 {
+            if let Poll::Ready(Some(_)) =
+                poll_outgoing(&mut from_protocol, 0, in_flight, capacity, &mut cx)
+            {
+                in_flight += 1;
+            }
+            async_std::task::sleep(Duration::from_millis(5)).await;
+        }
+
+        assert_eq!(in_flight, capacity);
+        assert_eq!(produced.load(Ordering::SeqCst), 2 * capacity);
+
+        drop(from_protocol);
+        producer.await;
+    }
+
+    #[async_std::test]
+    async fn broadcast_sent_as_computation() {
+        let mut remote = spawn_node("broadcast-type", vec![]).await;
+        let local = spawn_node("broadcast-type", vec![remote.address.clone()]).await;
+        let parties = vec![local.service.local_peer_id(), remote.address.peer_id];
+        wait_connected(&local.service, remote.address.peer_id).await;
+
+        let agent = TestAgent {
+            session_id: 0,
+            protocol_id: 0,
+            compute: Box::new(move |_parties, _incoming, outgoing| {
+                async move {
+                    let (sent_tx, sent_rx) = oneshot::channel();
+                    outgoing
+                        .send(OutgoingMessage {
+                            session_id: 0,
+                            body: vec![],
+                            to: MessageRouting::Broadcast,
+                            sent: Some(sent_tx),
+                        })
+                        .await?;
+                    sent_rx.await?;
+                    Ok(vec![])
+                }
+                .boxed()
+            }),
+        };
+        let result = execute(local, parties, agent, &RuntimeConfig::default());
+
+        let message = remote.room_rx.next().await.unwrap();
+        assert!(message.is_broadcast);
+        assert!(matches!(
+            message.context.message_type,
+            MessageType::Computation
+        ));
+        message
+            .pending_response
+            .send(OutgoingResponse {
+                result: Ok(vec![]),
+                sent_feedback: None,
+            })
+            .unwrap();
+
+        async_std::future::timeout(Duration::from_secs(10), result)
+            .await
+            .expect("computation didn't finish")
+            .unwrap()
+            .unwrap();
+    }
+
+    #[async_std::test]
+    #[traced_test]
+    async fn events_carry_session_span() {
+        let mut remote = spawn_node("session-span", vec![]).await;
+        let local = spawn_node("session-span", vec![remote.address.clone()]).await;
+        let parties = vec![local.service.local_peer_id(), remote.address.peer_id];
+        wait_connected(&local.service, remote.address.peer_id).await;
+
+        let agent = TestAgent {
+            session_id: 7,
+            protocol_id: 0,
+            compute: Box::new(move |parties, _incoming, outgoing| {
+                async move {
+                    info!("computation started");
+                    let remote_index = parties.index_of(parties.remotes().next().unwrap()).unwrap();
+                    let (sent_tx, sent_rx) = oneshot::channel();
+                    for (session_id, sent) in [(8, None), (7, Some(sent_tx))] {
+                        outgoing
+                            .send(OutgoingMessage {
+                                session_id,
+                                body: vec![],
+                                to: MessageRouting::PointToPoint(remote_index),
+                                sent,
+                            })
+                            .await?;
+                    }
+                    sent_rx.await?;
+                    Ok(vec![])
+                }
+                .boxed()
+            }),
+        };
+        let result = execute(local, parties, agent, &RuntimeConfig::default());
+
+        let message = remote.room_rx.next().await.unwrap();
+        assert_eq!(message.context.session_id, 7);
+        message
+            .pending_response
+            .send(OutgoingResponse {
+                result: Ok(vec![]),
+                sent_feedback: None,
+            })
+            .unwrap();
+        async_std::future::timeout(Duration::from_secs(10), result)
+            .await
+            .expect("computation didn't finish")
+            .unwrap()
+            .unwrap();
+
+        // Both the computation and the polling of its execution are within the session span.
+        logs_assert(|lines: &[&str]| {
+            for event in [
+                "computation started",
+                "dropping outgoing message of session 8",
+            ] {
+                if !lines.iter().any(|line| {
+                    line.contains(event) && line.contains("session{session_id=7 protocol_id=0")
+                }) {
+                    return Err(format!("\"{event}\" isn't logged within the session span"));
+                }
+            }
+            Ok(())
+        });
+    }
+}
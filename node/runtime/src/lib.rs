@@ -1,19 +1,35 @@
 #![feature(associated_type_defaults)]
 #![feature(async_closure)]
 
+mod auth;
+mod barrier;
+mod config;
 mod coordination;
 mod echo;
 mod error;
 mod execution;
+#[cfg(feature = "metrics")]
+mod metrics;
 mod negotiation;
 mod network_proxy;
 mod peerset;
 mod peerset_cacher;
+mod probe;
+mod rate_limit;
+mod retransmit;
 mod runtime;
+mod sequence;
+#[cfg(test)]
+mod testing;
 mod traits;
 
+pub use auth::*;
+pub use config::*;
 pub use error::*;
+#[cfg(feature = "metrics")]
+pub use metrics::RuntimeMetrics;
 pub use peerset::*;
 pub use peerset_cacher::*;
+pub use probe::*;
 pub use runtime::*;
 pub use traits::*;
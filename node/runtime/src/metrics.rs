@@ -0,0 +1,34 @@
+use mpc_p2p::metrics::{Metrics, MetricsVisitor};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Message throughput of the protocol executions.
+#[derive(Debug, Default)]
+pub struct RuntimeMetrics {
+    messages_sent: AtomicU64,
+    messages_received: AtomicU64,
+}
+
+impl RuntimeMetrics {
+    pub(crate) fn message_sent(&self) {
+        self.messages_sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn message_received(&self) {
+        self.messages_received.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl Metrics for RuntimeMetrics {
+    fn visit(&self, visitor: &mut dyn MetricsVisitor) {
+        visitor.counter(
+            "mpc_runtime_messages_sent_total",
+            "Number of messages sent by the protocol agents",
+            self.messages_sent.load(Ordering::Relaxed),
+        );
+        visitor.counter(
+            "mpc_runtime_messages_received_total",
+            "Number of messages delivered to the protocol agents",
+            self.messages_received.load(Ordering::Relaxed),
+        );
+    }
+}
@@ -1,7 +1,6 @@
-use crate::coordination::{LocalRpcMsg, Phase1Channel};
-use crate::network_proxy::ReceiverProxy;
 use crate::peerset::Peerset;
-use crate::{ComputeAgentAsync, PeersetMsg};
+use crate::{ComputeAgentAsync, PeersetMsg, RoomAuthenticator};
+use anyhow::anyhow;
 use async_std::stream;
 use async_std::stream::Interval;
 use futures::channel::{mpsc, oneshot};
@@ -9,13 +8,14 @@ use futures::Stream;
 use futures_util::stream::FuturesOrdered;
 use futures_util::FutureExt;
 use libp2p::PeerId;
-use log::info;
+use log::{info, warn};
 use mpc_p2p::{broadcast, MessageContext, MessageType, NetworkService, RoomId};
 use std::borrow::BorrowMut;
 use std::collections::HashSet;
 use std::future::Future;
 use std::io::{BufReader, BufWriter, Read, Write};
 use std::pin::Pin;
+use std::sync::Arc;
 use std::task::{Context, Poll};
 use std::time::Duration;
 use std::{io, iter};
@@ -24,7 +24,9 @@ pub(crate) struct NegotiationChan {
     rx: Option<mpsc::Receiver<broadcast::IncomingMessage>>,
     timeout: Interval,
     agent: Option<Box<dyn ComputeAgentAsync>>,
+    authenticator: Arc<dyn RoomAuthenticator>,
     state: Option<NegotiationState>,
+    starting: Option<Starting>,
 }
 
 struct NegotiationState {
@@ -38,21 +40,31 @@ struct NegotiationState {
     on_done: oneshot::Sender<anyhow::Result<Vec<u8>>>,
 }
 
+/// Session the remote parties are asked to start, each of them having to accept it.
+struct Starting {
+    parties: Peerset,
+    peerset_rx: mpsc::Receiver<PeersetMsg>,
+    acks: mpsc::Receiver<Result<(PeerId, Vec<u8>), broadcast::RequestFailure>>,
+    pending: usize,
+}
+
 impl NegotiationChan {
     pub fn new(
         room_id: RoomId,
-        room_rx: mpsc::Receiver<broadcast::IncomingMessage>,
+        session_rx: mpsc::Receiver<broadcast::IncomingMessage>,
         n: u16,
         args: Vec<u8>,
         service: NetworkService,
         agent: Box<dyn ComputeAgentAsync>,
         on_done: oneshot::Sender<anyhow::Result<Vec<u8>>>,
+        authenticator: Arc<dyn RoomAuthenticator>,
     ) -> Self {
         let local_peer_id = service.local_peer_id();
         Self {
-            rx: Some(room_rx),
+            rx: Some(session_rx),
             timeout: stream::interval(Duration::from_secs(15)),
             agent: Some(agent),
+            authenticator,
             state: Some(NegotiationState {
                 id: room_id,
                 n,
@@ -63,6 +75,7 @@ impl NegotiationChan {
                 pending_futures: Default::default(),
                 on_done,
             }),
+            starting: None,
         }
     }
 }
@@ -90,12 +103,56 @@ impl Future for NegotiationChan {
             }
         }
 
-        if let Some(rx) = responses.borrow_mut() {
+        if let Some(starting) = self.starting.as_mut() {
+            match starting.acks.try_next() {
+                Ok(Some(Ok(_))) => starting.pending -= 1,
+                Ok(Some(Err(e))) => {
+                    warn!("party refused to start computation: {e}");
+                    let _ = on_done.send(Err(anyhow!("party refused to start the session: {e}")));
+                    return Poll::Ready(NegotiationMsg::Abort);
+                }
+                _ => {}
+            }
+
+            if starting.pending == 0 {
+                let Starting {
+                    parties,
+                    peerset_rx,
+                    ..
+                } = self.starting.take().unwrap();
+                return Poll::Ready(NegotiationMsg::Start {
+                    room_id: id,
+                    agent: self.agent.take().unwrap(),
+                    on_done,
+                    room_receiver: self.rx.take().unwrap(),
+                    parties,
+                    peerset_rx,
+                    args,
+                });
+            }
+        } else if let Some(rx) = responses.borrow_mut() {
             match rx.try_next() {
-                Ok(Some(Ok((peer_id, _)))) => {
+                Ok(Some(Ok((peer_id, _)))) if !self.authenticator.authenticate(&peer_id, &id) => {
+                    warn!(
+                        "peer {} isn't allowed into room {}, disconnecting",
+                        peer_id.to_base58(),
+                        id.as_str()
+                    );
+                    let service = service.clone();
+                    pending_futures
+                        .push(async move { service.disconnect_peer(peer_id).await }.boxed());
+                }
+                Ok(Some(Ok((peer_id, payload)))) => {
+                    if let Err(e) =
+                        check_remote_version(self.agent.as_deref().unwrap(), &peer_id, &payload)
+                    {
+                        warn!("refusing to start computation: {e}");
+                        let _ = on_done.send(Err(e));
+                        return Poll::Ready(NegotiationMsg::Abort);
+                    }
                     peers.insert(peer_id);
                     if peers.len() == n as usize {
-                        let agent = self.agent.take().unwrap();
+                        let agent = self.agent.as_ref().unwrap();
                         let peers_iter = peers.clone().into_iter();
                         let (parties, peerset_rx) =
                             Peerset::new(peers_iter, service.local_peer_id());
@@ -103,19 +160,22 @@ impl Future for NegotiationChan {
                             parties: parties.clone(),
                             body: args.clone(),
                         };
+                        // Session is started once every remote party accepts it.
+                        let (acks_tx, acks) = mpsc::channel((n - 1) as usize);
                         pending_futures.push(
                             service
                                 .clone()
                                 .multicast_message_owned(
                                     id.clone(),
-                                    peers.clone().into_iter(),
+                                    parties.remotes().cloned().collect::<Vec<_>>().into_iter(),
                                     MessageContext {
                                         message_type: MessageType::Coordination,
                                         session_id: agent.session_id().into(),
                                         protocol_id: agent.protocol_id(),
+                                        seq: 0,
                                     },
                                     start_msg.to_bytes().unwrap(),
-                                    None,
+                                    Some(acks_tx),
                                 )
                                 .boxed(),
                         );
@@ -128,20 +188,11 @@ impl Future for NegotiationChan {
                             }
                         }
 
-                        let (receiver_proxy, room_receiver) = ReceiverProxy::new(
-                            id.clone(),
-                            self.rx.take().unwrap(),
-                            service.clone(),
-                            parties.clone(),
-                        );
-                        return Poll::Ready(NegotiationMsg::Start {
-                            agent,
-                            on_done,
-                            room_receiver,
-                            receiver_proxy,
+                        self.starting = Some(Starting {
                             parties,
                             peerset_rx,
-                            args,
+                            acks,
+                            pending: (n - 1) as usize,
                         });
                     }
                 }
@@ -159,8 +210,9 @@ impl Future for NegotiationChan {
                             message_type: MessageType::Coordination,
                             session_id: agent.session_id(),
                             protocol_id: agent.protocol_id(),
+                            seq: 0,
                         },
-                        vec![],
+                        encode_version(agent.protocol_version()),
                         Some(tx),
                     )
                     .boxed(),
@@ -168,10 +220,10 @@ impl Future for NegotiationChan {
             let _ = responses.insert(rx);
         }
 
-        // It took too long for peerset to be assembled  - reset to Phase 1.
+        // It took too long for peerset to be assembled - the session is dropped.
         if let Poll::Ready(Some(())) = Stream::poll_next(Pin::new(&mut self.timeout), cx) {
-            let (ch, tx) = Phase1Channel::new(id.clone(), self.rx.take().unwrap(), service.clone());
-            return Poll::Ready(NegotiationMsg::Abort(id.clone(), ch, tx));
+            let _ = on_done.send(Err(anyhow!("parties didn't join the session in time")));
+            return Poll::Ready(NegotiationMsg::Abort);
         }
 
         let _ = self.state.insert(NegotiationState {
@@ -193,15 +245,50 @@ impl Future for NegotiationChan {
 
 pub(crate) enum NegotiationMsg {
     Start {
+        room_id: RoomId,
         agent: Box<dyn ComputeAgentAsync>,
         on_done: oneshot::Sender<anyhow::Result<Vec<u8>>>,
         room_receiver: mpsc::Receiver<broadcast::IncomingMessage>,
-        receiver_proxy: ReceiverProxy,
         parties: Peerset,
         peerset_rx: mpsc::Receiver<PeersetMsg>,
         args: Vec<u8>,
     },
-    Abort(RoomId, Phase1Channel, oneshot::Sender<LocalRpcMsg>),
+    Abort,
+}
+
+/// Encodes the protocol version exchanged during negotiation.
+pub(crate) fn encode_version(version: u16) -> Vec<u8> {
+    let mut buffer = unsigned_varint::encode::u16_buffer();
+    unsigned_varint::encode::u16(version, &mut buffer).to_vec()
+}
+
+/// Decodes the protocol version exchanged during negotiation, `None` if the payload is malformed.
+pub(crate) fn decode_version(b: &[u8]) -> Option<u16> {
+    unsigned_varint::decode::u16(b)
+        .ok()
+        .map(|(version, _)| version)
+}
+
+/// Checks that the remote party responded with the same protocol version as the local `agent`.
+fn check_remote_version(
+    agent: &dyn ComputeAgentAsync,
+    peer_id: &PeerId,
+    payload: &[u8],
+) -> anyhow::Result<()> {
+    match decode_version(payload) {
+        Some(version) if version == agent.protocol_version() => Ok(()),
+        Some(version) => Err(anyhow!(
+            "party {} runs version {version} of protocol {}, local version is {}",
+            peer_id.to_base58(),
+            agent.protocol_id(),
+            agent.protocol_version()
+        )),
+        None => Err(anyhow!(
+            "party {} didn't report version of protocol {}",
+            peer_id.to_base58(),
+            agent.protocol_id()
+        )),
+    }
 }
 
 pub(crate) struct StartMsg {
@@ -231,11 +318,11 @@ impl StartMsg {
         let mut body = vec![0; length];
         io.read_exact(&mut body)?;
 
-        let (parties, rx) = Peerset::from_bytes(&*peerset_buffer, local_peer_id);
+        let (parties, rx) = Peerset::from_bytes(&*peerset_buffer, local_peer_id)?;
         Ok((Self { parties, body }, rx))
     }
 
-    fn to_bytes(self) -> io::Result<Vec<u8>> {
+    pub(crate) fn to_bytes(self) -> io::Result<Vec<u8>> {
         let b = vec![];
         let mut io = BufWriter::new(b);
 
@@ -267,21 +354,79 @@ impl StartMsg {
 
 #[cfg(test)]
 mod tests {
-    use crate::negotiation::StartMsg;
+    use crate::negotiation::{check_remote_version, decode_version, encode_version, StartMsg};
     use crate::peerset::Peerset;
-    use libp2p::PeerId;
-    use std::str::FromStr;
+    use crate::testing::peer_ids;
+    use crate::{ComputeAgentAsync, Error, IncomingEvent, OutgoingMessage, ProtocolAgentFactory};
+
+    struct VersionedAgent(u16);
+
+    #[async_trait::async_trait]
+    impl ComputeAgentAsync for VersionedAgent {
+        fn session_id(&self) -> u64 {
+            0
+        }
+
+        fn protocol_id(&self) -> u64 {
+            0
+        }
+
+        fn protocol_version(&self) -> u16 {
+            self.0
+        }
+
+        async fn compute(
+            self: Box<Self>,
+            _parties: Peerset,
+            _args: Vec<u8>,
+            _incoming: async_channel::Receiver<IncomingEvent>,
+            _outgoing: async_channel::Sender<OutgoingMessage>,
+        ) -> anyhow::Result<Vec<u8>> {
+            panic!("computation must not start with mismatching versions")
+        }
+    }
+
+    struct VersionedFactory(u16);
+
+    impl ProtocolAgentFactory for VersionedFactory {
+        fn make(&self, _protocol_id: u64) -> crate::Result<Box<dyn ComputeAgentAsync>> {
+            Ok(Box::new(VersionedAgent(self.0)))
+        }
+    }
+
+    #[test]
+    fn version_mismatch_rejected() {
+        let remote = peer_ids(1)[0];
+
+        // Joining party refuses to make an agent for another version.
+        assert!(matches!(
+            VersionedFactory(2).make_versioned(0, 1),
+            Err(Error::UnsupportedVersion {
+                requested: 1,
+                supported: 2,
+                ..
+            })
+        ));
+        assert!(VersionedFactory(1).make_versioned(0, 1).is_ok());
+
+        // Proposing party refuses to start once the remote reports another version.
+        let agent = VersionedAgent(1);
+        assert!(check_remote_version(&agent, &remote, &encode_version(1)).is_ok());
+        assert!(check_remote_version(&agent, &remote, &encode_version(2)).is_err());
+        assert!(check_remote_version(&agent, &remote, &[]).is_err());
+        assert_eq!(decode_version(&encode_version(300)), Some(300));
+    }
 
     #[test]
     fn start_msg_encoding() {
-        let peer_ids = vec![
-            PeerId::from_str("12D3KooWMQmcJA5raTtuxqAguM5CiXRhEDumLNmZQ7PmKZizjFBX").unwrap(),
-            PeerId::from_str("12D3KooWS4jk2BXKgyqygNEZScHSzntTKQCdHYiHRrZXiNE9mNHi").unwrap(),
-            PeerId::from_str("12D3KooWHYG3YsVs9hTwbgPKVrTrPQBKc8FnDhV6bsJ4W37eds8p").unwrap(),
-        ];
+        let peer_ids = peer_ids(3);
         let local_peer_id = peer_ids[0];
-        let (mut peerset, _) = Peerset::new(peer_ids.into_iter(), local_peer_id);
-        peerset.parties_indexes = vec![1, 2];
+        let (peerset, _) = Peerset::with_indices(
+            peer_ids.into_iter(),
+            local_peer_id,
+            vec![1.into(), 2.into(), 3.into()],
+        )
+        .unwrap();
         let start_msg = StartMsg {
             parties: peerset.clone(),
             body: vec![1, 2, 3],
@@ -1,69 +1,153 @@
-use crate::coordination::{LocalRpcMsg, Phase1Channel};
-use crate::peerset::Peerset;
-use futures::channel::{mpsc, oneshot};
+use futures::channel::mpsc;
+use futures::Stream;
+use log::warn;
 use mpc_p2p::broadcast::IncomingMessage;
-use mpc_p2p::{broadcast, NetworkService, RoomId};
+use mpc_p2p::RoomId;
+use std::collections::HashMap;
 use std::future::Future;
 use std::pin::Pin;
+use std::sync::{Arc, Mutex};
 use std::task::{Context, Poll};
 
+/// Number of messages buffered for each of the sessions until its execution takes them.
+pub(crate) const SESSION_BUFFER: usize = 16;
+
+/// Number of messages buffered for the room coordination.
+const COORDINATION_BUFFER: usize = 16;
+
+/// Receives the messages of a room for as long as it exists, passing the ones of the active
+/// sessions to their executions and the rest to the room coordination.
 pub(crate) struct ReceiverProxy {
     id: RoomId,
-    rx: Option<mpsc::Receiver<IncomingMessage>>,
-    tx: mpsc::Sender<broadcast::IncomingMessage>,
-    service: NetworkService,
-    parties: Peerset,
+    rx: mpsc::Receiver<IncomingMessage>,
+    router: Arc<Mutex<SessionRouter>>,
+    to_coordination: mpsc::Sender<IncomingMessage>,
 }
 
 impl ReceiverProxy {
+    /// Returns the proxy along with the router the sessions of the room are registered with and
+    /// the receiver of the coordination messages.
     pub fn new(
         room_id: RoomId,
         room_rx: mpsc::Receiver<IncomingMessage>,
-        service: NetworkService,
-        parties: Peerset,
-    ) -> (Self, mpsc::Receiver<IncomingMessage>) {
-        let (tx, rx) = mpsc::channel(parties.size() - 1);
+    ) -> (
+        Self,
+        Arc<Mutex<SessionRouter>>,
+        mpsc::Receiver<IncomingMessage>,
+    ) {
+        let router = Arc::new(Mutex::new(SessionRouter::default()));
+        let (to_coordination, from_proxy) = mpsc::channel(COORDINATION_BUFFER);
         (
             Self {
                 id: room_id,
-                rx: Some(room_rx),
-                tx,
-                service,
-                parties,
+                rx: room_rx,
+                router: router.clone(),
+                to_coordination,
             },
-            rx,
+            router,
+            from_proxy,
         )
     }
 }
 
 impl Future for ReceiverProxy {
-    type Output = (RoomId, Phase1Channel, oneshot::Sender<LocalRpcMsg>);
+    /// Resolves once the room channel is closed.
+    type Output = RoomId;
 
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        if self.tx.is_closed() {
-            let (ch, tx) = Phase1Channel::new(
-                self.id.clone(),
-                self.rx.take().unwrap(),
-                self.service.clone(),
-            );
-            return Poll::Ready((self.id.clone(), ch, tx));
+        loop {
+            match Stream::poll_next(Pin::new(&mut self.rx), cx) {
+                Poll::Ready(Some(msg)) => {
+                    let unrouted = self.router.lock().unwrap().route(msg);
+                    if let Some(msg) = unrouted {
+                        if let Err(e) = self.to_coordination.try_send(msg) {
+                            warn!(
+                                "dropping message from {}, room coordination can't take it",
+                                e.into_inner().peer_id.to_base58()
+                            );
+                        }
+                    }
+                }
+                Poll::Ready(None) => return Poll::Ready(self.id.clone()),
+                Poll::Pending => return Poll::Pending,
+            }
         }
+    }
+}
 
-        match self.rx.as_mut().unwrap().try_next() {
-            Ok(Some(mut msg)) => match self.parties.index_of(&msg.peer_id) {
-                Some(i) => {
-                    msg.peer_index = i;
-                    let _ = self.tx.try_send(msg);
-                }
-                None => {
-                    panic!("received message from unknown peer");
+/// Demultiplexes the messages received in a room to the sessions they belong to.
+#[derive(Default)]
+pub(crate) struct SessionRouter {
+    sessions: HashMap<u64, mpsc::Sender<IncomingMessage>>,
+}
+
+impl SessionRouter {
+    /// Registers the session, returning the receiver of its messages. `None` if the session is
+    /// already active.
+    pub fn register(
+        &mut self,
+        session_id: u64,
+        buffer: usize,
+    ) -> Option<mpsc::Receiver<IncomingMessage>> {
+        if matches!(self.sessions.get(&session_id), Some(tx) if !tx.is_closed()) {
+            return None;
+        }
+
+        let (tx, rx) = mpsc::channel(buffer);
+        self.sessions.insert(session_id, tx);
+        Some(rx)
+    }
+
+    /// Passes the message to its session, returning it back if the session isn't active.
+    pub fn route(&mut self, msg: IncomingMessage) -> Option<IncomingMessage> {
+        let session_id = msg.context.session_id;
+        match self.sessions.get_mut(&session_id) {
+            Some(tx) if !tx.is_closed() => {
+                if tx.try_send(msg).is_err() {
+                    warn!("dropping message, session {session_id} is behind on reading");
                 }
-            },
-            _ => {}
+                None
+            }
+            Some(_) => {
+                self.sessions.remove(&session_id);
+                Some(msg)
+            }
+            None => Some(msg),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::network_proxy::SessionRouter;
+    use crate::testing::{computation_context, peer_ids};
+    use futures::channel::oneshot;
+    use mpc_p2p::broadcast::IncomingMessage;
+
+    fn message(session_id: u64, payload: u8) -> IncomingMessage {
+        let (pending_response, _) = oneshot::channel();
+        IncomingMessage {
+            peer_id: peer_ids(1)[0],
+            peer_index: 1,
+            payload: vec![payload],
+            is_broadcast: false,
+            pending_response,
+            context: computation_context(session_id, 0),
         }
+    }
+
+    #[test]
+    fn unknown_sessions_returned() {
+        let mut router = SessionRouter::default();
+        let mut keygen_rx = router.register(1, 4).unwrap();
+        assert!(router.register(1, 4).is_none());
+
+        assert!(router.route(message(1, 10)).is_none());
+        assert_eq!(router.route(message(2, 20)).unwrap().payload, vec![20]);
+        assert_eq!(keygen_rx.try_next().unwrap().unwrap().payload, vec![10]);
 
-        // Wake this task to be polled again.
-        cx.waker().wake_by_ref();
-        Poll::Pending
+        drop(keygen_rx);
+        assert_eq!(router.route(message(1, 11)).unwrap().payload, vec![11]);
+        assert!(router.register(1, 4).is_some());
     }
 }
@@ -1,21 +1,114 @@
+use anyhow::anyhow;
 use futures::channel::{mpsc, oneshot};
 use futures_util::{SinkExt, StreamExt};
 use itertools::Itertools;
 use libp2p::PeerId;
-use log::{info, warn};
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
-use std::io::{BufReader, Read};
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::num::TryFromIntError;
 use std::ops::Index;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Time to wait for the runtime to serve a cache request, unless set by the runtime config.
+pub(crate) const DEFAULT_CACHE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Zero-based position of the party in the [`Peerset`].
+#[derive(
+    Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(transparent)]
+pub struct PartyIndex(u16);
+
+impl From<u16> for PartyIndex {
+    fn from(i: u16) -> Self {
+        Self(i)
+    }
+}
+
+impl From<PartyIndex> for u16 {
+    fn from(i: PartyIndex) -> Self {
+        i.0
+    }
+}
+
+impl From<PartyIndex> for usize {
+    fn from(i: PartyIndex) -> Self {
+        i.0 as usize
+    }
+}
+
+impl TryFrom<usize> for PartyIndex {
+    type Error = TryFromIntError;
+
+    fn try_from(i: usize) -> Result<Self, Self::Error> {
+        u16::try_from(i).map(Self)
+    }
+}
+
+impl Display for PartyIndex {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Index assigned to the party independently of its position in the [`Peerset`], e.g. at the key
+/// generation, so that it stays the same across sessions. Unlike [`PartyIndex`] it can't be used
+/// to look up the peer in the peerset.
+#[derive(
+    Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(transparent)]
+pub struct AssignedIndex(u16);
+
+impl From<u16> for AssignedIndex {
+    fn from(i: u16) -> Self {
+        Self(i)
+    }
+}
+
+impl From<AssignedIndex> for u16 {
+    fn from(i: AssignedIndex) -> Self {
+        i.0
+    }
+}
+
+impl TryFrom<usize> for AssignedIndex {
+    type Error = TryFromIntError;
+
+    fn try_from(i: usize) -> Result<Self, Self::Error> {
+        u16::try_from(i).map(Self)
+    }
+}
+
+impl Display for AssignedIndex {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Tag prefixing the peerset encoding with `u16` indexes. The legacy encoding with `u8` indexes
+/// has no tag and starts right away with the first peer id, whose multihash code is never `2`.
+const PEERSET_ENCODING_VERSION: u8 = 2;
+
+/// Length of the encoded ed25519 peer id.
+const PEER_ID_LEN: usize = 38;
 
 #[derive(Clone)]
 pub struct Peerset {
     local_peer_id: PeerId,
     session_peers: Vec<PeerId>,
-    pub parties_indexes: Vec<usize>,
+    pub(crate) parties_indexes: Vec<AssignedIndex>,
     to_runtime: mpsc::Sender<PeersetMsg>,
+    cache_timeout: Duration,
 }
 
-pub(crate) enum PeersetMsg {
+/// Cache requests sent by the [`Peerset`] to the runtime it's attached to.
+pub enum PeersetMsg {
     ReadFromCache(oneshot::Sender<anyhow::Result<Peerset>>),
     WriteToCache(Peerset, oneshot::Sender<anyhow::Result<()>>),
 }
@@ -28,89 +121,219 @@ impl Peerset {
         let (tx, rx) = mpsc::channel(1);
         let peers: Vec<_> = peers.sorted_by_key(|p| p.to_bytes()).collect();
 
-        (
-            Self {
-                local_peer_id,
-                parties_indexes: (0..peers.len()).collect(),
-                session_peers: peers,
-                to_runtime: tx,
-            },
-            rx,
-        )
+        let mut peerset = Self {
+            local_peer_id,
+            session_peers: peers,
+            parties_indexes: vec![],
+            to_runtime: tx,
+            cache_timeout: DEFAULT_CACHE_TIMEOUT,
+        };
+        peerset.derive_indexes();
+
+        (peerset, rx)
     }
 
+    /// Creates peerset where each of the `peers` is assigned an index at the same position in
+    /// `indices`. Fails if the lengths don't match, a peer is listed twice or two peers share
+    /// an index.
+    pub fn with_indices(
+        peers: impl Iterator<Item = PeerId>,
+        local_peer_id: PeerId,
+        indices: Vec<AssignedIndex>,
+    ) -> anyhow::Result<(Self, mpsc::Receiver<PeersetMsg>)> {
+        let peers: Vec<_> = peers.collect();
+        if peers.len() != indices.len() {
+            return Err(anyhow!(
+                "expected {} indices, got {}",
+                peers.len(),
+                indices.len()
+            ));
+        }
+
+        let mapping: HashMap<_, _> = peers.iter().cloned().zip(indices).collect();
+        if mapping.len() != peers.len() {
+            return Err(anyhow!("peers are expected to be listed once"));
+        }
+        let (mut peerset, rx) = Self::new(peers.into_iter(), local_peer_id);
+        peerset.assign_indexes(&mapping)?;
+
+        Ok((peerset, rx))
+    }
+
+    /// Decodes peerset encoded with [`Peerset::to_bytes`], or with the legacy untagged encoding
+    /// that has `u8` indexes, e.g. the one persisted by an older [`crate::PersistentCacher`].
     pub(crate) fn from_bytes(
         bytes: &[u8],
         local_peer_id: PeerId,
-    ) -> (Self, mpsc::Receiver<PeersetMsg>) {
+    ) -> io::Result<(Self, mpsc::Receiver<PeersetMsg>)> {
+        let (index_len, mut entries) = match bytes.split_first() {
+            Some((&PEERSET_ENCODING_VERSION, entries)) => (2, entries),
+            _ => (1, bytes),
+        };
+        let invalid_data = |e: String| io::Error::new(io::ErrorKind::InvalidData, e);
+
         let mut peers = vec![];
-        let mut active_indexes = vec![];
-        let mut reader = BufReader::new(bytes);
-
-        loop {
-            let mut buf = [0; 38];
-            if matches!(reader.read(&mut buf), Ok(n) if n == 38) {
-                peers.push(PeerId::from_bytes(&buf).unwrap())
-            } else {
-                break;
+        let mut indexes = vec![];
+        while !entries.is_empty() {
+            if entries.len() < PEER_ID_LEN + index_len {
+                return Err(invalid_data(format!(
+                    "truncated peerset entry of {} bytes",
+                    entries.len()
+                )));
             }
 
-            let mut buf = [0; 1];
-            reader.read(&mut buf).unwrap();
-            active_indexes.push(buf[0] as usize);
+            let (peer_id, rest) = entries.split_at(PEER_ID_LEN);
+            let (index, rest) = rest.split_at(index_len);
+            peers.push(PeerId::from_bytes(peer_id).map_err(|e| invalid_data(e.to_string()))?);
+            indexes.push(AssignedIndex(match *index {
+                [i] => i as u16,
+                [hi, lo] => u16::from_be_bytes([hi, lo]),
+                _ => unreachable!("index is either one or two bytes long"),
+            }));
+            entries = rest;
         }
 
-        let peers: Vec<_> = peers.into_iter().sorted_by_key(|p| p.to_bytes()).collect();
+        Self::with_indices(peers.into_iter(), local_peer_id, indexes)
+            .map_err(|e| invalid_data(e.to_string()))
+    }
 
-        let (tx, rx) = mpsc::channel(1);
-        (
-            Self {
-                local_peer_id,
-                session_peers: peers,
-                parties_indexes: active_indexes,
-                to_runtime: tx,
-            },
-            rx,
-        )
+    /// Attaches peerset to the runtime through `to_runtime`, e.g. after it was deserialized.
+    pub fn attach(&mut self, to_runtime: mpsc::Sender<PeersetMsg>) {
+        self.to_runtime = to_runtime;
+    }
+
+    /// Sets the time to wait for the runtime to serve the cache requests.
+    pub(crate) fn set_cache_timeout(&mut self, timeout: Duration) {
+        self.cache_timeout = timeout;
     }
 
     pub async fn recover_from_cache(&mut self) -> anyhow::Result<()> {
+        let cache = self.cache_request(PeersetMsg::ReadFromCache).await?;
+
+        // Cached index is looked up by the peer id, so reordered peers keep theirs.
+        self.assign_indexes(&cache.index_mapping())
+            .map_err(|e| anyhow!("failed recovering peerset from cache: {e}"))
+    }
+
+    pub async fn save_to_cache(&mut self) -> anyhow::Result<()> {
+        let peerset = self.clone();
+        self.cache_request(|tx| PeersetMsg::WriteToCache(peerset, tx))
+            .await
+    }
+
+    /// Sends the cache request to the runtime and waits for its response, failing if the
+    /// runtime is gone or doesn't respond within the cache timeout.
+    async fn cache_request<T>(
+        &mut self,
+        request: impl FnOnce(oneshot::Sender<anyhow::Result<T>>) -> PeersetMsg,
+    ) -> anyhow::Result<T> {
+        let timeout = self.cache_timeout;
         let (tx, rx) = oneshot::channel();
-        let _ = self.to_runtime.send(PeersetMsg::ReadFromCache(tx)).await;
-        let cache = rx.await.expect("runtime expected to serve protocol")?;
-        let mut parties_indexes = vec![];
-        for peer_id in self.session_peers.iter().sorted_by_key(|p| p.to_bytes()) {
-            match cache.index_of(peer_id) {
-                Some(i) => {
-                    parties_indexes.push(cache.parties_indexes[i as usize]);
-                }
-                None => {
-                    warn!(
-                        "Peer {} does not appear in the peerset cache, skipping.",
-                        peer_id.to_base58()
-                    )
-                }
+        let response = async {
+            self.to_runtime
+                .send(request(tx))
+                .await
+                .map_err(|e| anyhow!("failed sending cache request to runtime: {e}"))?;
+            rx.await
+                .map_err(|_| anyhow!("runtime dropped the cache request"))?
+        };
+
+        async_std::future::timeout(timeout, response)
+            .await
+            .map_err(|_| anyhow!("runtime didn't serve the cache request within {timeout:?}"))?
+    }
+
+    /// Returns indexes assigned to the session peers, in the order of the peers. These aren't
+    /// positions in the peerset, use [`Peerset::index_of`] for those.
+    pub fn parties_indexes(&self) -> &[AssignedIndex] {
+        &self.parties_indexes
+    }
+
+    /// Returns the index assigned to each of the session peers.
+    pub fn index_mapping(&self) -> HashMap<PeerId, AssignedIndex> {
+        self.session_peers
+            .iter()
+            .cloned()
+            .zip(self.parties_indexes.iter().cloned())
+            .collect()
+    }
+
+    /// Assigns each of the session peers the rank of its id bytes among the ids of all session
+    /// peers, so that every party derives the same index for it whatever order it got the peers in.
+    pub fn derive_indexes(&mut self) {
+        let mapping = self
+            .session_peers
+            .iter()
+            .sorted_by_key(|p| p.to_bytes())
+            .enumerate()
+            .map(|(i, p)| {
+                let index =
+                    AssignedIndex::try_from(i).expect("peerset size is expected to fit u16");
+                (*p, index)
+            })
+            .collect();
+
+        self.assign_indexes(&mapping)
+            .expect("derived indexes are distinct");
+    }
+
+    /// Assigns indexes from the caller-supplied `mapping` to the session peers, independently
+    /// of their order. Fails if any of the peers isn't mapped or two peers share an index.
+    pub fn assign_indexes(
+        &mut self,
+        mapping: &HashMap<PeerId, AssignedIndex>,
+    ) -> anyhow::Result<()> {
+        let mut parties_indexes = Vec::with_capacity(self.session_peers.len());
+        let mut assigned = HashSet::new();
+
+        for peer_id in self.session_peers.iter() {
+            let index = *mapping
+                .get(peer_id)
+                .ok_or_else(|| anyhow!("no index for peer {}", peer_id.to_base58()))?;
+            if !assigned.insert(index) {
+                return Err(anyhow!("index {index} is assigned to multiple peers"));
             }
+            parties_indexes.push(index);
         }
 
         self.parties_indexes = parties_indexes;
         Ok(())
     }
 
-    pub async fn save_to_cache(&mut self) -> anyhow::Result<()> {
-        let (tx, rx) = oneshot::channel();
-        let _ = self
-            .to_runtime
-            .send(PeersetMsg::WriteToCache(self.clone(), tx))
-            .await;
-        rx.await.expect("runtime expected to serve protocol")
+    /// Describes how the session membership changed from `self` to `other`, e.g. from the cached
+    /// peerset to the current one. Indexes of the retained peers are taken from both sides.
+    pub fn diff(&self, other: &Peerset) -> PeersetDiff {
+        let old_indexes = self.index_mapping();
+        let new_indexes = other.index_mapping();
+        let mut diff = PeersetDiff::default();
+
+        for (peer_id, new_index) in other.session_peers.iter().zip(other.parties_indexes.iter()) {
+            match old_indexes.get(peer_id) {
+                Some(old_index) => diff.retained.push(RetainedPeer {
+                    peer_id: *peer_id,
+                    old_index: *old_index,
+                    new_index: *new_index,
+                }),
+                None => diff.added.push((*peer_id, *new_index)),
+            }
+        }
+
+        for (peer_id, old_index) in self.session_peers.iter().zip(self.parties_indexes.iter()) {
+            if !new_indexes.contains_key(peer_id) {
+                diff.removed.push((*peer_id, *old_index));
+            }
+        }
+
+        diff
     }
 
-    pub fn index_of(&self, peer_id: &PeerId) -> Option<u16> {
+    /// Returns position of the peer in the session, which [`Peerset`] can be indexed by. It isn't
+    /// the assigned index, see [`Peerset::parties_indexes`] for that one.
+    pub fn index_of(&self, peer_id: &PeerId) -> Option<PartyIndex> {
         self.session_peers
             .iter()
             .position(|elem| *elem == *peer_id)
-            .map(|i| i as u16)
+            .map(|i| PartyIndex::try_from(i).expect("peerset size is expected to fit u16"))
     }
 
     pub fn size(&self) -> usize {
@@ -118,11 +341,11 @@ impl Peerset {
     }
 
     pub fn to_bytes(&self) -> Vec<u8> {
-        let mut buf = vec![];
+        let mut buf = vec![PEERSET_ENCODING_VERSION];
 
-        for (i, peer_id) in self.session_peers.iter().enumerate() {
+        for (peer_id, index) in self.session_peers.iter().zip(self.parties_indexes.iter()) {
             buf.append(&mut peer_id.to_bytes());
-            buf.push(self.parties_indexes[i] as u8);
+            buf.extend_from_slice(&index.0.to_be_bytes());
         }
 
         buf
@@ -136,6 +359,13 @@ impl Peerset {
             .map(|(_i, p)| p.clone())
     }
 
+    /// Returns iterator over the remote peers in the set, without consuming it.
+    pub fn remotes(&self) -> impl Iterator<Item = &PeerId> + '_ {
+        self.session_peers
+            .iter()
+            .filter(move |p| **p != self.local_peer_id)
+    }
+
     pub fn local_peer_id(&self) -> &PeerId {
         return &self.local_peer_id;
     }
@@ -145,11 +375,74 @@ impl Peerset {
     }
 }
 
-impl Index<u16> for Peerset {
+impl Index<PartyIndex> for Peerset {
     type Output = PeerId;
 
-    fn index(&self, index: u16) -> &Self::Output {
-        &self.session_peers[index as usize]
+    fn index(&self, index: PartyIndex) -> &Self::Output {
+        &self.session_peers[usize::from(index)]
+    }
+}
+
+/// Difference between two peersets, see [`Peerset::diff`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PeersetDiff {
+    /// Peers that joined, with their new indexes.
+    pub added: Vec<(PeerId, AssignedIndex)>,
+    /// Peers that left, with their old indexes.
+    pub removed: Vec<(PeerId, AssignedIndex)>,
+    /// Peers present in both peersets.
+    pub retained: Vec<RetainedPeer>,
+}
+
+/// Peer present in both peersets, along with its index in each.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RetainedPeer {
+    pub peer_id: PeerId,
+    pub old_index: AssignedIndex,
+    pub new_index: AssignedIndex,
+}
+
+/// Serialized form of the [`Peerset`], peer ids are encoded as base58 strings.
+#[derive(Serialize, Deserialize)]
+struct PeersetRepr {
+    local_peer_id: String,
+    session_peers: Vec<String>,
+    parties_indexes: Vec<AssignedIndex>,
+}
+
+impl Serialize for Peerset {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        PeersetRepr {
+            local_peer_id: self.local_peer_id.to_base58(),
+            session_peers: self.session_peers.iter().map(|p| p.to_base58()).collect(),
+            parties_indexes: self.parties_indexes.clone(),
+        }
+        .serialize(serializer)
+    }
+}
+
+/// Deserialized peerset isn't attached to any runtime, so its cache operations will fail
+/// until it's attached to a channel with [`Peerset::attach`].
+impl<'de> Deserialize<'de> for Peerset {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let repr = PeersetRepr::deserialize(deserializer)?;
+        let parse_peer_id = |s: &str| PeerId::from_str(s).map_err(D::Error::custom);
+
+        let local_peer_id = parse_peer_id(&repr.local_peer_id)?;
+        let session_peers = repr
+            .session_peers
+            .iter()
+            .map(|p| parse_peer_id(p))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let (peerset, _) = Self::with_indices(
+            session_peers.into_iter(),
+            local_peer_id,
+            repr.parties_indexes,
+        )
+        .map_err(D::Error::custom)?;
+
+        Ok(peerset)
     }
 }
 
@@ -164,21 +457,26 @@ impl IntoIterator for Peerset {
 
 #[cfg(test)]
 mod tests {
-    use crate::peerset::Peerset;
+    use crate::peerset::{AssignedIndex, PartyIndex, Peerset, PeersetMsg, RetainedPeer};
+    use crate::testing::peer_ids;
+    use futures::channel::mpsc;
+    use futures_util::StreamExt;
     use libp2p::PeerId;
-    use std::str::FromStr;
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    fn indexes(indexes: &[u16]) -> Vec<AssignedIndex> {
+        indexes.iter().map(|i| AssignedIndex::from(*i)).collect()
+    }
 
     #[test]
     fn peerset_encoding() {
-        let peer_ids = vec![
-            PeerId::from_str("12D3KooWMQmcJA5raTtuxqAguM5CiXRhEDumLNmZQ7PmKZizjFBX").unwrap(),
-            PeerId::from_str("12D3KooWHYG3YsVs9hTwbgPKVrTrPQBKc8FnDhV6bsJ4W37eds8p").unwrap(),
-        ];
+        let peer_ids = peer_ids(2);
         let local_peer_id = peer_ids[0];
-        let (mut peerset, _) = Peerset::new(peer_ids.into_iter(), local_peer_id);
-        peerset.parties_indexes = vec![0, 2];
+        let (peerset, _) =
+            Peerset::with_indices(peer_ids.into_iter(), local_peer_id, indexes(&[0, 300])).unwrap();
         let encoded = peerset.to_bytes();
-        let (decoded, _) = Peerset::from_bytes(&*encoded, local_peer_id);
+        let (decoded, _) = Peerset::from_bytes(&*encoded, local_peer_id).unwrap();
 
         println!(
             "original: {:?}, {:?}",
@@ -191,4 +489,352 @@ mod tests {
 
         assert_eq!(peerset.parties_indexes, decoded.parties_indexes);
     }
+
+    #[test]
+    fn legacy_peerset_decoded() {
+        let peer_ids = peer_ids(2);
+        let mut encoded = vec![];
+        for (peer_id, index) in peer_ids.iter().zip([3u8, 1]) {
+            encoded.append(&mut peer_id.to_bytes());
+            encoded.push(index);
+        }
+
+        let (decoded, _) = Peerset::from_bytes(&encoded, peer_ids[0]).unwrap();
+        assert_eq!(
+            decoded.index_mapping()[&peer_ids[0]],
+            AssignedIndex::from(3)
+        );
+        assert_eq!(
+            decoded.index_mapping()[&peer_ids[1]],
+            AssignedIndex::from(1)
+        );
+    }
+
+    #[test]
+    fn truncated_peerset_rejected() {
+        let peer_ids = peer_ids(2);
+        let local_peer_id = peer_ids[0];
+        let (peerset, _) = Peerset::new(peer_ids.into_iter(), local_peer_id);
+        let encoded = peerset.to_bytes();
+
+        assert!(Peerset::from_bytes(&encoded[..encoded.len() - 1], local_peer_id).is_err());
+        assert!(Peerset::from_bytes(&encoded[..20], local_peer_id).is_err());
+    }
+
+    #[test]
+    fn peerset_json_roundtrip() {
+        let peer_ids = peer_ids(3);
+        let local_peer_id = peer_ids[1];
+        let (peerset, _) =
+            Peerset::with_indices(peer_ids.into_iter(), local_peer_id, indexes(&[2, 0, 1]))
+                .unwrap();
+
+        let json = serde_json::to_string(&peerset).unwrap();
+        assert!(json.contains(&local_peer_id.to_base58()));
+
+        let decoded: Peerset = serde_json::from_str(&json).unwrap();
+        assert_eq!(peerset.local_peer_id, decoded.local_peer_id);
+        assert_eq!(peerset.session_peers, decoded.session_peers);
+        assert_eq!(peerset.parties_indexes, decoded.parties_indexes);
+    }
+
+    #[test]
+    fn invalid_peerset_json_rejected() {
+        let peer_ids: Vec<_> = peer_ids(2).iter().map(PeerId::to_base58).collect();
+        let (local_peer_id, remote_peer_id) = (peer_ids[0].as_str(), peer_ids[1].as_str());
+        let json = |session_peers: &[&str], parties_indexes: &[u16]| {
+            serde_json::json!({
+                "local_peer_id": local_peer_id,
+                "session_peers": session_peers,
+                "parties_indexes": parties_indexes,
+            })
+        };
+
+        assert!(
+            serde_json::from_value::<Peerset>(json(&[local_peer_id, remote_peer_id], &[0, 1]))
+                .is_ok()
+        );
+        assert!(
+            serde_json::from_value::<Peerset>(json(&[local_peer_id, remote_peer_id], &[0]))
+                .is_err()
+        );
+        assert!(
+            serde_json::from_value::<Peerset>(json(&[local_peer_id, remote_peer_id], &[1, 1]))
+                .is_err()
+        );
+        assert!(
+            serde_json::from_value::<Peerset>(json(&[local_peer_id, local_peer_id], &[0, 1]))
+                .is_err()
+        );
+    }
+
+    #[async_std::test]
+    async fn deserialized_peerset_attached() {
+        let peer_ids = peer_ids(2);
+        let local_peer_id = peer_ids[0];
+        let (peerset, _) = Peerset::new(peer_ids.into_iter(), local_peer_id);
+        let session_peers = peerset.session_peers.clone();
+        let json = serde_json::to_string(&peerset).unwrap();
+
+        let mut decoded: Peerset = serde_json::from_str(&json).unwrap();
+        assert!(decoded.save_to_cache().await.is_err());
+
+        let (to_runtime, mut from_peerset) = mpsc::channel(1);
+        decoded.attach(to_runtime);
+        async_std::task::spawn(async move {
+            if let Some(PeersetMsg::WriteToCache(peerset, tx)) = from_peerset.next().await {
+                assert_eq!(peerset.session_peers, session_peers);
+                let _ = tx.send(Ok(()));
+            }
+        });
+
+        decoded.save_to_cache().await.unwrap();
+    }
+
+    #[test]
+    fn with_indices_assigned_to_peers() {
+        let peer_ids = peer_ids(3);
+        let (peerset, _) = Peerset::with_indices(
+            peer_ids.clone().into_iter(),
+            peer_ids[0],
+            indexes(&[4, 1, 7]),
+        )
+        .unwrap();
+
+        assert_eq!(
+            peerset.index_mapping()[&peer_ids[0]],
+            AssignedIndex::from(4)
+        );
+        assert_eq!(
+            peerset.index_mapping()[&peer_ids[1]],
+            AssignedIndex::from(1)
+        );
+        assert_eq!(
+            peerset.index_mapping()[&peer_ids[2]],
+            AssignedIndex::from(7)
+        );
+    }
+
+    #[test]
+    fn with_indices_length_mismatch() {
+        let peer_ids = peer_ids(2);
+
+        assert!(
+            Peerset::with_indices(peer_ids.clone().into_iter(), peer_ids[0], indexes(&[0]))
+                .is_err()
+        );
+        assert!(Peerset::with_indices(
+            peer_ids.clone().into_iter(),
+            peer_ids[0],
+            indexes(&[0, 1, 2])
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn with_indices_duplicate_index() {
+        let peer_ids = peer_ids(2);
+
+        assert!(
+            Peerset::with_indices(peer_ids.clone().into_iter(), peer_ids[0], indexes(&[1, 1]))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn party_index_conversions() {
+        let i = PartyIndex::try_from(7usize).unwrap();
+        assert_eq!(u16::from(i), 7);
+        assert_eq!(usize::from(i), 7);
+        assert_eq!(PartyIndex::from(7u16), i);
+        assert!(PartyIndex::try_from(usize::from(u16::MAX) + 1).is_err());
+    }
+
+    #[test]
+    fn borrowing_remotes() {
+        let peer_ids = peer_ids(4);
+        let local_peer_id = peer_ids[2];
+        let (peerset, _) = Peerset::new(peer_ids.into_iter(), local_peer_id);
+
+        let remotes: Vec<_> = peerset.remotes().cloned().collect();
+        assert_eq!(remotes.len(), 3);
+        assert!(!remotes.contains(&local_peer_id));
+        assert_eq!(remotes, peerset.clone().remotes_iter().collect::<Vec<_>>());
+    }
+
+    #[async_std::test]
+    async fn cached_indexes_preserved_when_reordered() {
+        let peer_ids = peer_ids(3);
+        let local_peer_id = peer_ids[0];
+
+        // Cached peerset lists the peers in another order than the session does.
+        let cached_mapping: HashMap<_, _> =
+            peer_ids.iter().cloned().zip(indexes(&[2, 0, 1])).collect();
+        let (cache, _) = Peerset::with_indices(
+            peer_ids.iter().rev().cloned(),
+            local_peer_id,
+            peer_ids.iter().rev().map(|p| cached_mapping[p]).collect(),
+        )
+        .unwrap();
+
+        let (mut peerset, mut peerset_rx) = Peerset::new(peer_ids.into_iter(), local_peer_id);
+        async_std::task::spawn(async move {
+            if let Some(PeersetMsg::ReadFromCache(tx)) = peerset_rx.next().await {
+                let _ = tx.send(Ok(cache));
+            }
+        });
+        peerset.recover_from_cache().await.unwrap();
+
+        assert_eq!(peerset.index_mapping(), cached_mapping);
+
+        let (decoded, _) = Peerset::from_bytes(&peerset.to_bytes(), local_peer_id).unwrap();
+        assert_eq!(decoded.index_mapping(), cached_mapping);
+    }
+
+    #[async_std::test]
+    async fn recover_fails_for_uncached_peer() {
+        let peer_ids = peer_ids(3);
+        let local_peer_id = peer_ids[0];
+        let (cache, _) = Peerset::new(peer_ids[..2].iter().cloned(), local_peer_id);
+
+        let (mut peerset, mut peerset_rx) = Peerset::new(peer_ids.into_iter(), local_peer_id);
+        let parties_indexes = peerset.parties_indexes.clone();
+        async_std::task::spawn(async move {
+            if let Some(PeersetMsg::ReadFromCache(tx)) = peerset_rx.next().await {
+                let _ = tx.send(Ok(cache));
+            }
+        });
+
+        assert!(peerset.recover_from_cache().await.is_err());
+        assert_eq!(peerset.parties_indexes, parties_indexes);
+    }
+
+    #[test]
+    fn assign_indexes_from_mapping() {
+        let peer_ids = peer_ids(2);
+        let (mut peerset, _) = Peerset::new(peer_ids.clone().into_iter(), peer_ids[0]);
+
+        let mapping: HashMap<_, _> = peer_ids.iter().cloned().zip(indexes(&[5, 3])).collect();
+        peerset.assign_indexes(&mapping).unwrap();
+        assert_eq!(peerset.index_mapping(), mapping);
+
+        let duplicate: HashMap<_, _> = peer_ids.iter().cloned().zip(indexes(&[1, 1])).collect();
+        assert!(peerset.assign_indexes(&duplicate).is_err());
+        assert!(peerset.assign_indexes(&HashMap::new()).is_err());
+    }
+
+    #[test]
+    fn derived_indexes_independent_of_order() {
+        let peer_ids = peer_ids(3);
+        let (mut forward, _) = Peerset::with_indices(
+            peer_ids.clone().into_iter(),
+            peer_ids[0],
+            indexes(&[0, 1, 2]),
+        )
+        .unwrap();
+        let (mut reversed, _) = Peerset::with_indices(
+            peer_ids.clone().into_iter().rev(),
+            peer_ids[0],
+            indexes(&[0, 1, 2]),
+        )
+        .unwrap();
+        assert_ne!(forward.index_mapping(), reversed.index_mapping());
+
+        forward.derive_indexes();
+        reversed.derive_indexes();
+        assert_eq!(forward.index_mapping(), reversed.index_mapping());
+
+        let mut sorted = peer_ids.clone();
+        sorted.sort_by_key(|p| p.to_bytes());
+        for (i, peer_id) in sorted.iter().enumerate() {
+            assert_eq!(
+                forward.index_mapping()[peer_id],
+                AssignedIndex::try_from(i).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn diff_peer_added() {
+        let peer_ids = peer_ids(3);
+        let (cached, _) =
+            Peerset::with_indices(peer_ids[..2].iter().cloned(), peer_ids[0], indexes(&[7, 5]))
+                .unwrap();
+        let (current, _) =
+            Peerset::with_indices(peer_ids.iter().cloned(), peer_ids[0], indexes(&[7, 5, 9]))
+                .unwrap();
+        let joined = peer_ids[2];
+
+        let diff = cached.diff(&current);
+        assert_eq!(diff.added, vec![(joined, AssignedIndex::from(9))]);
+        assert!(diff.removed.is_empty());
+        assert_eq!(diff.retained.len(), 2);
+    }
+
+    #[test]
+    fn diff_peer_removed() {
+        let peer_ids = peer_ids(3);
+        let left = peer_ids[1];
+        let (cached, _) =
+            Peerset::with_indices(peer_ids.iter().cloned(), peer_ids[0], indexes(&[7, 5, 9]))
+                .unwrap();
+        let (current, _) = Peerset::with_indices(
+            peer_ids.iter().cloned().filter(|p| *p != left),
+            peer_ids[0],
+            indexes(&[7, 9]),
+        )
+        .unwrap();
+
+        let diff = cached.diff(&current);
+        assert!(diff.added.is_empty());
+        assert_eq!(diff.removed, vec![(left, AssignedIndex::from(5))]);
+        assert_eq!(diff.retained.len(), 2);
+    }
+
+    #[test]
+    fn diff_reordered_membership() {
+        let peer_ids = peer_ids(3);
+        let mapping: HashMap<_, _> = peer_ids.iter().cloned().zip(indexes(&[1, 2, 0])).collect();
+        let (cached, _) = Peerset::with_indices(
+            peer_ids.iter().rev().cloned(),
+            peer_ids[0],
+            peer_ids.iter().rev().map(|p| mapping[p]).collect(),
+        )
+        .unwrap();
+        let (mut current, _) = Peerset::new(peer_ids.clone().into_iter(), peer_ids[0]);
+        current.assign_indexes(&mapping).unwrap();
+
+        let diff = cached.diff(&current);
+        assert!(diff.added.is_empty());
+        assert!(diff.removed.is_empty());
+        assert_eq!(diff.retained.len(), 3);
+        for peer_id in peer_ids {
+            let index = mapping[&peer_id];
+            assert!(diff.retained.contains(&RetainedPeer {
+                peer_id,
+                old_index: index,
+                new_index: index,
+            }));
+        }
+    }
+
+    #[async_std::test]
+    async fn cache_requests_fail_without_runtime() {
+        let peer_ids = peer_ids(3);
+        let (mut peerset, peerset_rx) = Peerset::new(peer_ids.clone().into_iter(), peer_ids[0]);
+        drop(peerset_rx);
+
+        assert!(peerset.recover_from_cache().await.is_err());
+        assert!(peerset.save_to_cache().await.is_err());
+    }
+
+    #[async_std::test]
+    async fn cache_requests_time_out() {
+        let peer_ids = peer_ids(3);
+        let (mut peerset, _peerset_rx) = Peerset::new(peer_ids.clone().into_iter(), peer_ids[0]);
+        peerset.set_cache_timeout(Duration::from_millis(10));
+
+        assert!(peerset.recover_from_cache().await.is_err());
+        assert!(peerset.save_to_cache().await.is_err());
+    }
 }
@@ -40,7 +40,8 @@ impl PeersetCacher for PersistentCacher {
         let buf = fs::read(self.path.join(room_id.as_str()))
             .map_err(|e| anyhow!("error reading peerset cache file: {e}"))?;
 
-        let (peerset, _) = Peerset::from_bytes(&*buf, self.local_peer_id);
+        let (peerset, _) = Peerset::from_bytes(&*buf, self.local_peer_id)
+            .map_err(|e| anyhow!("error decoding peerset cache file: {e}"))?;
 
         Ok(peerset)
     }
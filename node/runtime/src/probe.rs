@@ -0,0 +1,61 @@
+use crate::{PartyIndex, Peerset};
+use futures::future::join_all;
+use mpc_p2p::{NetworkService, ProbeResult};
+use std::collections::HashMap;
+
+/// Probes every remote party in the `parties`, e.g. to check they are reachable before
+/// starting a session with them.
+pub async fn probe_all(
+    network_service: &NetworkService,
+    parties: &Peerset,
+) -> HashMap<PartyIndex, ProbeResult> {
+    let probes = parties.remotes().filter_map(|peer_id| {
+        let index = parties.index_of(peer_id)?;
+        Some(async move { (index, network_service.probe_peer(*peer_id).await) })
+    });
+
+    join_all(probes).await.into_iter().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::peerset::Peerset;
+    use crate::probe::probe_all;
+    use crate::testing::{peer_ids, spawn_node};
+    use mpc_p2p::{MultiaddrWithPeerId, ProbeResult};
+    use std::net::TcpListener;
+
+    #[async_std::test]
+    async fn parties_reachability() {
+        let remote = spawn_node("probe", vec![]).await;
+        let closed_port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let offline = MultiaddrWithPeerId {
+            multiaddr: format!("/ip4/127.0.0.1/tcp/{}", closed_port)
+                .parse()
+                .unwrap(),
+            peer_id: peer_ids(1)[0],
+        };
+        let local = spawn_node("probe", vec![remote.address.clone(), offline.clone()]).await;
+
+        let local_peer_id = local.service.local_peer_id();
+        let (parties, _) = Peerset::new(
+            vec![local_peer_id, remote.address.peer_id, offline.peer_id].into_iter(),
+            local_peer_id,
+        );
+        let reachability = probe_all(&local.service, &parties).await;
+
+        assert_eq!(reachability.len(), 2);
+        assert!(matches!(
+            reachability[&parties.index_of(&remote.address.peer_id).unwrap()],
+            ProbeResult::Reachable(_) | ProbeResult::Connected
+        ));
+        assert!(matches!(
+            reachability[&parties.index_of(&offline.peer_id).unwrap()],
+            ProbeResult::Unreachable(_)
+        ));
+    }
+}
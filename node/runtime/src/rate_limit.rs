@@ -0,0 +1,151 @@
+use crate::RateLimit;
+use libp2p::PeerId;
+use std::collections::{HashMap, VecDeque};
+use std::time::Instant;
+
+/// Limits the rate of the messages received from each of the peers with a token bucket.
+/// Messages above the limit wait for the bucket to be refilled, up to `burst` of them per peer.
+pub(crate) struct RateLimiter<T> {
+    limit: RateLimit,
+    buckets: HashMap<PeerId, Bucket>,
+    delayed: HashMap<PeerId, VecDeque<T>>,
+}
+
+/// Decision on the message received from a peer.
+pub(crate) enum Admission<T> {
+    /// Message is within the limit.
+    Allowed(T),
+    /// Message waits for the bucket to be refilled, see [`RateLimiter::take_ready`].
+    Delayed,
+    /// Message exceeds the limit with the delayed ones too, and should be refused.
+    Refused(T),
+}
+
+struct Bucket {
+    /// Messages that can be accepted right away.
+    tokens: f64,
+    /// Time the tokens were last refilled.
+    refilled_at: Instant,
+}
+
+impl<T> RateLimiter<T> {
+    pub fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            buckets: HashMap::new(),
+            delayed: HashMap::new(),
+        }
+    }
+
+    /// Decides on the `message` received from `peer_id` at `now`. Peer's messages are admitted
+    /// in order, so the message waits behind the delayed ones even if a token is available.
+    pub fn admit(&mut self, peer_id: &PeerId, message: T, now: Instant) -> Admission<T> {
+        let delayed = self.delayed.get(peer_id).map_or(0, VecDeque::len);
+        if delayed == 0 && self.allow(peer_id, now) {
+            return Admission::Allowed(message);
+        }
+
+        if delayed >= self.limit.burst as usize {
+            return Admission::Refused(message);
+        }
+
+        self.delayed.entry(*peer_id).or_default().push_back(message);
+        Admission::Delayed
+    }
+
+    /// Takes the delayed messages that fit into the limit by `now`.
+    pub fn take_ready(&mut self, now: Instant) -> Vec<T> {
+        let mut ready = vec![];
+        let peers: Vec<_> = self.delayed.keys().cloned().collect();
+        for peer_id in peers {
+            while self.delayed[&peer_id].front().is_some() && self.allow(&peer_id, now) {
+                ready.extend(self.delayed.get_mut(&peer_id).unwrap().pop_front());
+            }
+            if self.delayed[&peer_id].is_empty() {
+                self.delayed.remove(&peer_id);
+            }
+        }
+
+        ready
+    }
+
+    /// Takes a token from the bucket of `peer_id`, returns `false` if the message received at
+    /// `now` exceeds the limit and should be dropped.
+    pub fn allow(&mut self, peer_id: &PeerId, now: Instant) -> bool {
+        let burst = self.limit.burst as f64;
+        let bucket = self.buckets.entry(*peer_id).or_insert(Bucket {
+            tokens: burst,
+            refilled_at: now,
+        });
+
+        let elapsed = now.saturating_duration_since(bucket.refilled_at);
+        bucket.tokens =
+            (bucket.tokens + elapsed.as_secs_f64() * self.limit.messages_per_sec as f64).min(burst);
+        bucket.refilled_at = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rate_limit::{Admission, RateLimiter};
+    use crate::testing::peer_ids;
+    use crate::RateLimit;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn burst_above_limit_dropped() {
+        let peers = peer_ids(2);
+        let (flooding, honest) = (peers[0], peers[1]);
+        let mut limiter = RateLimiter::<()>::new(RateLimit {
+            messages_per_sec: 10,
+            burst: 5,
+        });
+        let now = Instant::now();
+
+        let accepted = (0..20).filter(|_| limiter.allow(&flooding, now)).count();
+        assert_eq!(accepted, 5);
+        assert!(limiter.allow(&honest, now));
+
+        // Tokens are refilled at the configured rate.
+        let later = now + Duration::from_millis(200);
+        assert!(limiter.allow(&flooding, later));
+        assert!(limiter.allow(&flooding, later));
+        assert!(!limiter.allow(&flooding, later));
+    }
+
+    #[test]
+    fn messages_above_limit_delayed() {
+        let peer_id = peer_ids(1)[0];
+        let mut limiter = RateLimiter::new(RateLimit {
+            messages_per_sec: 10,
+            burst: 2,
+        });
+        let now = Instant::now();
+
+        let admitted: Vec<_> = (0..5)
+            .map(|i| match limiter.admit(&peer_id, i, now) {
+                Admission::Allowed(i) => Some(i),
+                Admission::Delayed => None,
+                Admission::Refused(_) => Some(-1),
+            })
+            .collect();
+        assert_eq!(admitted, [Some(0), Some(1), None, None, Some(-1)]);
+        assert!(limiter.take_ready(now).is_empty());
+
+        // Delayed messages are admitted in order as the tokens are refilled, ahead of new ones.
+        let later = now + Duration::from_millis(100);
+        assert_eq!(limiter.take_ready(later), [2]);
+        assert!(matches!(
+            limiter.admit(&peer_id, 5, later),
+            Admission::Delayed
+        ));
+        assert_eq!(limiter.take_ready(later + Duration::from_secs(1)), [3, 5]);
+    }
+}
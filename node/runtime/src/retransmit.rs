@@ -0,0 +1,269 @@
+use futures::channel::{mpsc, oneshot};
+use futures::StreamExt;
+use libp2p::PeerId;
+use log::warn;
+use mpc_p2p::{broadcast, MessageContext};
+use std::collections::{HashMap, HashSet};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+type ResponseReceiver = mpsc::Receiver<Result<(PeerId, Vec<u8>), broadcast::RequestFailure>>;
+
+/// Point-to-point message that is kept until the remote party acknowledges it.
+pub(crate) struct PendingMessage {
+    pub peer_id: PeerId,
+    /// Context of the message, including its sequence number, reused by every attempt to let
+    /// the remote drop duplicates if an earlier attempt did land.
+    pub context: MessageContext,
+    pub body: Vec<u8>,
+    pub sent: Option<oneshot::Sender<()>>,
+    /// Number of times the message has been resent.
+    pub retries: usize,
+}
+
+/// Keeps point-to-point messages until they are delivered, parking the failed ones until their
+/// receiver reconnects or, while it stays connected, `retry_delay` passes, for at most
+/// `max_retries` times.
+pub(crate) struct Retransmitter {
+    in_flight: Vec<(PendingMessage, ResponseReceiver)>,
+    /// Failed messages of each receiver, along with the time they are due to be resent.
+    parked: HashMap<PeerId, Vec<(Instant, PendingMessage)>>,
+    /// Receivers whose messages aren't resent until they reconnect.
+    disconnected: HashSet<PeerId>,
+    max_retries: usize,
+    retry_delay: Duration,
+}
+
+impl Retransmitter {
+    pub fn new(max_retries: usize, retry_delay: Duration) -> Self {
+        Self {
+            in_flight: vec![],
+            parked: HashMap::new(),
+            disconnected: HashSet::new(),
+            max_retries,
+            retry_delay,
+        }
+    }
+
+    /// Tracks the delivery of the `message` that has just been sent.
+    pub fn track(&mut self, message: PendingMessage, response: ResponseReceiver) {
+        self.in_flight.push((message, response));
+    }
+
+    /// Returns number of the messages waiting for the response.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    /// Returns number of the messages waiting to be resent.
+    pub fn parked(&self) -> usize {
+        self.parked.values().map(Vec::len).sum()
+    }
+
+    /// Polls the responses of the messages in flight. Delivered messages are acknowledged
+    /// through `sent`, the failed ones are parked, or dropped once out of retries.
+    pub fn poll_responses(&mut self, cx: &mut Context<'_>) {
+        let mut i = 0;
+        while i < self.in_flight.len() {
+            let result = match self.in_flight[i].1.poll_next_unpin(cx) {
+                Poll::Ready(Some(result)) => result,
+                Poll::Ready(None) => Err(broadcast::RequestFailure::Obsolete),
+                Poll::Pending => {
+                    i += 1;
+                    continue;
+                }
+            };
+
+            let (mut message, _) = self.in_flight.swap_remove(i);
+            match result {
+                Ok(_) => {
+                    if let Some(tx) = message.sent.take() {
+                        let _ = tx.send(());
+                    }
+                }
+                Err(e) if message.retries < self.max_retries => {
+                    warn!(
+                        "delivery to {} failed, resending once reconnected or in {:?}: {e}",
+                        message.peer_id.to_base58(),
+                        self.retry_delay
+                    );
+                    self.parked
+                        .entry(message.peer_id)
+                        .or_default()
+                        .push((Instant::now() + self.retry_delay, message));
+                }
+                Err(e) => {
+                    // Dropping `sent` lets the protocol observe the failure.
+                    warn!(
+                        "delivery to {} failed, no retries left: {e}",
+                        message.peer_id.to_base58()
+                    );
+                }
+            }
+        }
+    }
+
+    /// Pauses resending the messages parked for `peer_id` until it reconnects.
+    pub fn peer_disconnected(&mut self, peer_id: PeerId) {
+        self.disconnected.insert(peer_id);
+    }
+
+    /// Takes the messages parked for `peer_id` to be resent once it has reconnected.
+    pub fn take_parked(&mut self, peer_id: &PeerId) -> Vec<PendingMessage> {
+        self.disconnected.remove(peer_id);
+        let parked = self.parked.remove(peer_id).unwrap_or_default();
+        retried(parked.into_iter().map(|(_, message)| message))
+    }
+
+    /// Takes the parked messages that are due to be resent by `now`, e.g. when the receiver
+    /// stays connected but the delivery has failed. Messages of disconnected receivers wait
+    /// for [`Retransmitter::take_parked`] instead.
+    pub fn take_due(&mut self, now: Instant) -> Vec<PendingMessage> {
+        let mut due = vec![];
+        let disconnected = &self.disconnected;
+        self.parked.retain(|peer_id, parked| {
+            if disconnected.contains(peer_id) {
+                return true;
+            }
+
+            let mut i = 0;
+            while i < parked.len() {
+                if parked[i].0 <= now {
+                    due.push(parked.swap_remove(i).1);
+                } else {
+                    i += 1;
+                }
+            }
+            !parked.is_empty()
+        });
+
+        retried(due.into_iter())
+    }
+}
+
+/// Counts another attempt of sending each of the `messages`.
+fn retried(messages: impl Iterator<Item = PendingMessage>) -> Vec<PendingMessage> {
+    messages
+        .map(|mut message| {
+            message.retries += 1;
+            message
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::retransmit::{PendingMessage, Retransmitter};
+    use crate::sequence::{SequenceBuffer, Sequenced};
+    use crate::testing::{peer_ids, pending_message};
+    use crate::PartyIndex;
+    use futures::channel::{mpsc, oneshot};
+    use mpc_p2p::broadcast::RequestFailure;
+    use std::task::Context;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn resent_after_reconnect_arrives_once() {
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let remote = peer_ids(1)[0];
+        let (sent_tx, mut sent_rx) = oneshot::channel();
+        let mut retransmitter = Retransmitter::new(1, Duration::from_secs(1));
+        let mut remote_buffer = SequenceBuffer::new(16, Duration::from_secs(5));
+        let mut delivered = vec![];
+
+        let message = pending_message(remote, vec![1, 2, 3], sent_tx);
+
+        // First attempt lands, but the connection drops before it is acknowledged.
+        if let Sequenced::Ready(messages) = remote_buffer.push(
+            PartyIndex::from(0),
+            false,
+            message.context.seq,
+            message.body.clone(),
+        ) {
+            delivered.extend(messages);
+        }
+        let (mut res_tx, res_rx) = mpsc::channel(1);
+        retransmitter.track(message, res_rx);
+        res_tx.try_send(Err(RequestFailure::NotConnected)).unwrap();
+        retransmitter.poll_responses(&mut cx);
+        assert_eq!(sent_rx.try_recv(), Ok(None));
+
+        // Connection is restored, so the message is resent.
+        let mut parked = retransmitter.take_parked(&remote);
+        assert_eq!(parked.len(), 1);
+        let message = parked.pop().unwrap();
+        assert!(matches!(
+            remote_buffer.push(
+                PartyIndex::from(0),
+                false,
+                message.context.seq,
+                message.body.clone()
+            ),
+            Sequenced::Duplicate(_)
+        ));
+        let (mut res_tx, res_rx) = mpsc::channel(1);
+        retransmitter.track(message, res_rx);
+        res_tx.try_send(Ok((remote, vec![]))).unwrap();
+        retransmitter.poll_responses(&mut cx);
+
+        assert_eq!(sent_rx.try_recv(), Ok(Some(())));
+        assert_eq!(delivered, vec![vec![1, 2, 3]]);
+        assert!(retransmitter.take_parked(&remote).is_empty());
+    }
+
+    #[test]
+    fn sent_dropped_without_retries_left() {
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let remote = peer_ids(1)[0];
+        let (sent_tx, sent_rx) = oneshot::channel();
+        let mut retransmitter = Retransmitter::new(0, Duration::from_secs(1));
+
+        let (mut res_tx, res_rx) = mpsc::channel(1);
+        retransmitter.track(pending_message(remote, vec![], sent_tx), res_rx);
+        res_tx.try_send(Err(RequestFailure::NotConnected)).unwrap();
+        retransmitter.poll_responses(&mut cx);
+
+        assert!(retransmitter.take_parked(&remote).is_empty());
+        assert!(futures::executor::block_on(sent_rx).is_err());
+    }
+
+    #[test]
+    fn resent_after_delay_only_while_connected() {
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let remote = peer_ids(1)[0];
+        let delay = Duration::from_secs(1);
+        let (sent_tx, sent_rx) = oneshot::channel();
+        let mut retransmitter = Retransmitter::new(2, delay);
+        let message = pending_message(remote, vec![], sent_tx);
+        let mut refuse = |message: PendingMessage, retransmitter: &mut Retransmitter| {
+            let (mut res_tx, res_rx) = mpsc::channel(1);
+            retransmitter.track(message, res_rx);
+            res_tx.try_send(Err(RequestFailure::Refused)).unwrap();
+            retransmitter.poll_responses(&mut cx);
+        };
+
+        // Remote refuses the first attempt without disconnecting, so it is resent after the delay.
+        refuse(message, &mut retransmitter);
+        assert!(retransmitter.take_due(Instant::now()).is_empty());
+        let mut due = retransmitter.take_due(Instant::now() + delay);
+        assert_eq!(due.len(), 1);
+
+        // Remote disconnects after refusing the retry, which then waits for it to reconnect.
+        refuse(due.pop().unwrap(), &mut retransmitter);
+        retransmitter.peer_disconnected(remote);
+        assert!(retransmitter
+            .take_due(Instant::now() + 2 * delay)
+            .is_empty());
+        assert_eq!(retransmitter.parked(), 1);
+        let message = retransmitter.take_parked(&remote).pop().unwrap();
+
+        // Once out of retries, the message is dropped.
+        refuse(message, &mut retransmitter);
+        assert!(retransmitter.take_due(Instant::now() + delay).is_empty());
+        assert_eq!(retransmitter.parked(), 0);
+        assert!(futures::executor::block_on(sent_rx).is_err());
+    }
+}
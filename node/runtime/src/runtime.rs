@@ -2,20 +2,28 @@ use crate::coordination::LocalRpcMsg;
 use crate::coordination::Phase2Msg;
 use crate::echo::EchoGadget;
 use crate::execution::ProtocolExecution;
-use crate::negotiation::NegotiationMsg;
+#[cfg(feature = "metrics")]
+use crate::metrics::RuntimeMetrics;
+use crate::negotiation::{decode_version, encode_version, NegotiationMsg};
+use crate::network_proxy::ReceiverProxy;
 
-use crate::{coordination, PersistentCacher, ProtocolAgentFactory};
+use crate::peerset::Peerset;
+use crate::{
+    coordination, Error, PersistentCacher, ProtocolAgentFactory, RoomAuthenticator, RuntimeConfig,
+};
 use anyhow::anyhow;
 use blake2::Digest;
 use futures::channel::{mpsc, oneshot};
 use futures::StreamExt;
-use futures_util::stream::FuturesUnordered;
+use futures_util::stream::{FuturesUnordered, SelectAll};
 use futures_util::{select, FutureExt, SinkExt};
-use log::error;
+use libp2p::PeerId;
+use log::{error, warn};
 use mpc_p2p::broadcast::OutgoingResponse;
 use mpc_p2p::{broadcast, NetworkService, RoomId};
-use std::collections::hash_map::Entry;
 use std::collections::HashMap;
+#[cfg(feature = "metrics")]
+use std::sync::Arc;
 
 pub enum RuntimeMessage {
     RequestComputation {
@@ -30,6 +38,8 @@ pub enum RuntimeMessage {
 #[derive(Clone)]
 pub struct RuntimeService {
     to_runtime: mpsc::Sender<RuntimeMessage>,
+    #[cfg(feature = "metrics")]
+    metrics: Arc<RuntimeMetrics>,
 }
 
 impl RuntimeService {
@@ -52,6 +62,12 @@ impl RuntimeService {
             .await
             .expect("request computation expected");
     }
+
+    /// Returns message throughput metrics of the runtime.
+    #[cfg(feature = "metrics")]
+    pub fn metrics(&self) -> Arc<RuntimeMetrics> {
+        self.metrics.clone()
+    }
 }
 
 pub struct RuntimeDaemon<TFactory> {
@@ -60,6 +76,9 @@ pub struct RuntimeDaemon<TFactory> {
     agents_factory: TFactory,
     from_service: mpsc::Receiver<RuntimeMessage>,
     peerset_cacher: PersistentCacher,
+    config: RuntimeConfig,
+    #[cfg(feature = "metrics")]
+    metrics: Arc<RuntimeMetrics>,
 }
 
 impl<TFactory: ProtocolAgentFactory + Send + Unpin> RuntimeDaemon<TFactory> {
@@ -68,8 +87,13 @@ impl<TFactory: ProtocolAgentFactory + Send + Unpin> RuntimeDaemon<TFactory> {
         rooms: impl Iterator<Item = (RoomId, mpsc::Receiver<broadcast::IncomingMessage>)>,
         agents_factory: TFactory,
         peerset_cacher: PersistentCacher,
-    ) -> (Self, RuntimeService) {
+        config: RuntimeConfig,
+    ) -> crate::Result<(Self, RuntimeService)> {
+        config.validate()?;
+
         let (tx, rx) = mpsc::channel(2);
+        #[cfg(feature = "metrics")]
+        let metrics = Arc::new(RuntimeMetrics::default());
 
         let worker = Self {
             network_service,
@@ -77,18 +101,27 @@ impl<TFactory: ProtocolAgentFactory + Send + Unpin> RuntimeDaemon<TFactory> {
             from_service: rx,
             agents_factory,
             peerset_cacher,
+            config,
+            #[cfg(feature = "metrics")]
+            metrics: metrics.clone(),
         };
 
-        let service = RuntimeService { to_runtime: tx };
+        let service = RuntimeService {
+            to_runtime: tx,
+            #[cfg(feature = "metrics")]
+            metrics,
+        };
 
-        (worker, service)
+        Ok((worker, service))
     }
 
     pub async fn run(self) {
         let mut protocol_executions = FuturesUnordered::new();
         let mut network_proxies = FuturesUnordered::new();
-        let mut rooms_coordination = FuturesUnordered::new();
+        let mut rooms_coordination = SelectAll::new();
         let mut rooms_rpc = HashMap::new();
+        let mut joining_sessions = FuturesUnordered::new();
+        let mut negotiations = FuturesUnordered::new();
 
         let Self {
             network_service,
@@ -96,21 +129,26 @@ impl<TFactory: ProtocolAgentFactory + Send + Unpin> RuntimeDaemon<TFactory> {
             agents_factory,
             from_service,
             peerset_cacher,
+            config,
+            #[cfg(feature = "metrics")]
+            metrics,
         } = self;
 
         for (room_id, rx) in rooms.into_iter() {
-            let (ch, tx) =
-                coordination::Phase1Channel::new(room_id.clone(), rx, network_service.clone());
+            let (proxy, router, coordination_rx) = ReceiverProxy::new(room_id.clone(), rx);
+            let (ch, tx) = coordination::Phase1Channel::new(
+                room_id.clone(),
+                coordination_rx,
+                router,
+                network_service.clone(),
+            );
+            network_proxies.push(proxy);
             rooms_coordination.push(ch);
             rooms_rpc.insert(room_id, tx);
         }
 
         let mut service_messages = from_service.fuse();
 
-        // loop {
-        //     match rooms_coordination.select_next_some().await
-        // }
-
         loop {
             select! {
                 srv_msg = service_messages.select_next_some() => {
@@ -122,25 +160,22 @@ impl<TFactory: ProtocolAgentFactory + Send + Unpin> RuntimeDaemon<TFactory> {
                             args,
                             on_done,
                         } => {
-                            match rooms_rpc.entry(room_id) {
-                                Entry::Occupied(e) => {
+                            match rooms_rpc.get_mut(&room_id) {
+                                Some(on_rpc) => {
                                     let agent = match agents_factory.make(protocol_id) {
                                         Ok(a) => a,
                                         Err(_) => {
-                                            on_done.send(Err(anyhow!("unknown protocol")));
+                                            let _ = on_done.send(Err(anyhow!("unknown protocol")));
                                             continue;
                                         }
                                     };
-                                    let on_rpc = e.remove();
 
-                                    if on_rpc.is_canceled() {
-                                        on_done.send(Err(anyhow!("protocol is busy")));
-                                    } else {
-                                        on_rpc.send(LocalRpcMsg{n, args, agent, on_done});
+                                    if let Err(e) = on_rpc.try_send(LocalRpcMsg{n, args, agent, on_done, authenticator: config.authenticator.clone()}) {
+                                        let _ = e.into_inner().on_done.send(Err(anyhow!("room is busy")));
                                     }
                                 }
-                                Entry::Vacant(_) => {
-                                    error!("{:?}", on_done.send(Err(anyhow!("protocol is busy"))));
+                                None => {
+                                    let _ = on_done.send(Err(anyhow!("unknown room")));
                                 }
                             }
                         },
@@ -148,113 +183,563 @@ impl<TFactory: ProtocolAgentFactory + Send + Unpin> RuntimeDaemon<TFactory> {
                 },
                 coord_msg = rooms_coordination.select_next_some() => match coord_msg {
                     coordination::Phase1Msg::FromRemote {
-                        peer_id: _,
+                        peer_id,
                         protocol_id,
-                        session_id: _,
-                        payload: _,
+                        session_id,
+                        payload,
                         response_tx,
                         channel,
                     } => {
-                        let agent = match agents_factory.make(protocol_id) {
+                        if !config.authenticator.authenticate(&peer_id, channel.room_id()) {
+                            warn!("peer {} isn't allowed into room {}, disconnecting", peer_id.to_base58(), channel.room_id().as_str());
+                            network_service.disconnect_peer(peer_id).await;
+                            continue;
+                        }
+
+                        let requested_version = match decode_version(&payload) {
+                            Some(version) => version,
+                            None => {
+                                error!("refusing to join computation: {} didn't request a version of protocol {protocol_id}", peer_id.to_base58());
+                                let _ = response_tx.send(OutgoingResponse {
+                                    result: Err(()),
+                                    sent_feedback: None,
+                                });
+                                continue;
+                            }
+                        };
+                        let agent = match agents_factory.make_versioned(protocol_id, requested_version) {
                             Ok(a) => a,
-                            Err(_) => {
-                                let (id, ch, tx) = channel.abort();
-                                rooms_coordination.push(ch);
-                                rooms_rpc.insert(id, tx);
+                            Err(e) => {
+                                error!("refusing to join computation: {e}");
+                                // Let the proposer know which version is run here.
+                                let result = match e {
+                                    Error::UnsupportedVersion { supported, .. } => Ok(encode_version(supported)),
+                                    _ => Err(()),
+                                };
+                                let _ = response_tx.send(OutgoingResponse {
+                                    result,
+                                    sent_feedback: None,
+                                });
                                 continue;
                             }
                         };
 
-                        response_tx.send(OutgoingResponse {
-                            result: Ok(vec![]), // todo: real negotiation logic
+                        // Local agent tags its messages with its own session, so it must be the negotiated one.
+                        if agent.session_id() != session_id {
+                            error!(
+                                "refusing to join session {session_id}, protocol {protocol_id} runs in session {}",
+                                agent.session_id()
+                            );
+                            let _ = response_tx.send(OutgoingResponse {
+                                result: Err(()),
+                                sent_feedback: None,
+                            });
+                            continue;
+                        }
+
+                        let _ = response_tx.send(OutgoingResponse {
+                            result: Ok(encode_version(agent.protocol_version())),
                             sent_feedback: None,
                         });
 
-                        match channel.await {
-                            Phase2Msg::Start {
-                                room_id,
-                                room_receiver,
-                                receiver_proxy,
-                                parties,
-                                peerset_rx,
-                                init_body,
-                            } => {
-                                network_proxies.push(receiver_proxy);
-                                let (echo, echo_tx) = EchoGadget::new(parties.size());
-                                protocol_executions.push(echo.wrap_execution(ProtocolExecution::new(
-                                    room_id,
-                                    init_body,
-                                    agent,
-                                    network_service.clone(),
-                                    parties,
-                                    peerset_rx,
-                                    peerset_cacher.clone(),
-                                    room_receiver,
-                                    echo_tx,
-                                    None,
-                                )));
-                            }
-                            Phase2Msg::Abort(room_id, ch, tx) => {
-                                rooms_rpc.entry(room_id).and_modify(|e| *e = tx);
-                                rooms_coordination.push(ch);
+                        joining_sessions.push(channel.map(move |msg| (agent, msg)));
+                    }
+                    coordination::Phase1Msg::FromLocal { negotiation } => {
+                        negotiations.push(negotiation);
+                    }
+                },
+                (agent, phase2_msg) = joining_sessions.select_next_some() => match phase2_msg {
+                    Phase2Msg::Start {
+                        room_id,
+                        room_receiver,
+                        parties,
+                        peerset_rx,
+                        init_body,
+                        response_tx,
+                    } => {
+                        // Proposer could have assembled the session with any peers, so each of them is checked.
+                        let rejected = rejected_parties(&*config.authenticator, &room_id, &parties);
+                        if !rejected.is_empty() {
+                            for peer_id in rejected {
+                                warn!("peer {} isn't allowed into room {}, disconnecting", peer_id.to_base58(), room_id.as_str());
+                                network_service.disconnect_peer(peer_id).await;
                             }
+                            let _ = response_tx.send(OutgoingResponse {
+                                result: Err(()),
+                                sent_feedback: None,
+                            });
+                            continue;
                         }
+                        let _ = response_tx.send(OutgoingResponse {
+                            result: Ok(vec![]),
+                            sent_feedback: None,
+                        });
+
+                        let (echo, echo_tx) = EchoGadget::new(parties.size());
+                        let execution = ProtocolExecution::new(
+                            room_id,
+                            init_body,
+                            agent,
+                            network_service.clone(),
+                            parties,
+                            peerset_rx,
+                            peerset_cacher.clone(),
+                            room_receiver,
+                            echo_tx,
+                            &config,
+                            None,
+                        );
+                        #[cfg(feature = "metrics")]
+                        let execution = execution.with_metrics(metrics.clone());
+                        protocol_executions.push(echo.wrap_execution(execution));
                     }
-                    coordination::Phase1Msg::FromLocal {
-                        id,
-                        n,
-                        negotiation,
+                    Phase2Msg::Abort => {}
+                },
+                negotiation_msg = negotiations.select_next_some() => match negotiation_msg {
+                    NegotiationMsg::Start {
+                        room_id,
+                        agent,
+                        on_done,
+                        room_receiver,
+                        parties,
+                        peerset_rx,
+                        args,
                     } => {
-                        match negotiation.await {
-                            NegotiationMsg::Start {
-                                agent,
-                                on_done,
-                                room_receiver,
-                                receiver_proxy,
-                                parties,
-                                peerset_rx,
-                                args,
-                            } => {
-                                network_proxies.push(receiver_proxy);
-                                let (echo, echo_tx) = EchoGadget::new(n as usize);
-                                protocol_executions.push(echo.wrap_execution(ProtocolExecution::new(
-                                    id,
-                                    args,
-                                    agent,
-                                    network_service.clone(),
-                                    parties,
-                                    peerset_rx,
-                                    peerset_cacher.clone(),
-                                    room_receiver,
-                                    echo_tx,
-                                    Some(on_done),
-                                )));
-                            }
-                            NegotiationMsg::Abort(room_id, phase1, rpc_tx) => {
-                                rooms_coordination.push(phase1);
-                                rooms_rpc.insert(room_id, rpc_tx);
-                                continue;
-                            }
-                        };
+                        let (echo, echo_tx) = EchoGadget::new(parties.size());
+                        let execution = ProtocolExecution::new(
+                            room_id,
+                            args,
+                            agent,
+                            network_service.clone(),
+                            parties,
+                            peerset_rx,
+                            peerset_cacher.clone(),
+                            room_receiver,
+                            echo_tx,
+                            &config,
+                            Some(on_done),
+                        );
+                        #[cfg(feature = "metrics")]
+                        let execution = execution.with_metrics(metrics.clone());
+                        protocol_executions.push(echo.wrap_execution(execution));
                     }
+                    NegotiationMsg::Abort => {}
                 },
                 exec_res = protocol_executions.select_next_some() => match exec_res {
                     Ok(_) => {}
                     Err(e) => {error!("error during computation: {e}")}
                 },
-                (room_id, phase1, rpc_tx) = network_proxies.select_next_some() => {
-                    rooms_coordination.push(phase1);
-                    rooms_rpc.insert(room_id, rpc_tx);
+                room_id = network_proxies.select_next_some() => {
+                    warn!("room {} is closed", room_id.as_str());
+                    rooms_rpc.remove(&room_id);
                 }
             }
+        }
+    }
+}
+
+/// Returns the remote parties of the session that aren't allowed into the room.
+fn rejected_parties(
+    authenticator: &dyn RoomAuthenticator,
+    room_id: &RoomId,
+    parties: &Peerset,
+) -> Vec<PeerId> {
+    parties
+        .remotes()
+        .filter(|peer_id| !authenticator.authenticate(peer_id, room_id))
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::negotiation::{encode_version, StartMsg};
+    use crate::peerset::Peerset;
+    use crate::testing::{
+        computation_context, spawn_node, spawn_runtime, wait_connected, TestAgent, TestFactory,
+    };
+    use crate::{
+        AllowList, ComputeAgentAsync, IncomingEvent, MessageRouting, OutgoingMessage,
+        ProtocolAgentFactory, RateLimit, RuntimeConfig,
+    };
+    use async_std::sync::Barrier;
+    use futures::channel::{mpsc, oneshot};
+    use futures_util::{FutureExt, StreamExt};
+    use mpc_p2p::{MessageContext, MessageType, NetworkEvent};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    /// Makes the agents running the protocol of each id in the session with the same id. Each of
+    /// them sends its protocol id to the remote party, then waits for the other session to be
+    /// running as well before returning what it has received.
+    fn exchanging_factory() -> TestFactory {
+        let barrier = Arc::new(Barrier::new(2));
+        TestFactory(Arc::new(move |protocol_id| {
+            let barrier = barrier.clone();
+            TestAgent {
+                session_id: protocol_id,
+                protocol_id,
+                compute: Box::new(move |parties, incoming, outgoing| {
+                    async move {
+                        let remote = parties.index_of(parties.remotes().next().unwrap()).unwrap();
+                        let (sent_tx, sent_rx) = oneshot::channel();
+                        outgoing
+                            .send(OutgoingMessage {
+                                session_id: protocol_id,
+                                body: vec![protocol_id as u8],
+                                to: MessageRouting::PointToPoint(remote),
+                                sent: Some(sent_tx),
+                            })
+                            .await?;
+                        let received = loop {
+                            if let IncomingEvent::Message(message) = incoming.recv().await? {
+                                break message.body;
+                            }
+                        };
+                        sent_rx.await?;
+                        barrier.wait().await;
+                        Ok(received)
+                    }
+                    .boxed()
+                }),
+            }
+        }))
+    }
+
+    /// Agent running the given protocol version, flagging once its computation is run.
+    struct VersionedAgent {
+        version: u16,
+        computed: Arc<AtomicBool>,
+    }
+
+    #[async_trait::async_trait]
+    impl ComputeAgentAsync for VersionedAgent {
+        fn session_id(&self) -> u64 {
+            0
+        }
+
+        fn protocol_id(&self) -> u64 {
+            0
+        }
+
+        fn protocol_version(&self) -> u16 {
+            self.version
+        }
+
+        async fn compute(
+            self: Box<Self>,
+            _parties: Peerset,
+            _args: Vec<u8>,
+            _incoming: async_channel::Receiver<IncomingEvent>,
+            _outgoing: async_channel::Sender<OutgoingMessage>,
+        ) -> anyhow::Result<Vec<u8>> {
+            self.computed.store(true, Ordering::SeqCst);
+            Ok(vec![])
+        }
+    }
+
+    struct VersionedFactory(u16, Arc<AtomicBool>);
 
-            // if let Ok(Some(srv_msg)) = self.from_service.try_next() {
-            //     let daemon = service_messages.get_ref();
-            //
-            //     match srv_msg {
-            //
-            //     }
-            // }
+    impl ProtocolAgentFactory for VersionedFactory {
+        fn make(&self, _protocol_id: u64) -> crate::Result<Box<dyn ComputeAgentAsync>> {
+            Ok(Box::new(VersionedAgent {
+                version: self.0,
+                computed: self.1.clone(),
+            }))
         }
     }
+
+    #[async_std::test]
+    async fn sessions_run_concurrently_in_room() {
+        let joining = spawn_node("sessions", vec![]).await;
+        let proposer = spawn_node("sessions", vec![joining.address.clone()]).await;
+        let room_id = proposer.room_id.clone();
+        wait_connected(&proposer.service, joining.address.peer_id).await;
+
+        let mut runtime = spawn_runtime(proposer, exchanging_factory(), RuntimeConfig::default());
+        let _joining_runtime =
+            spawn_runtime(joining, exchanging_factory(), RuntimeConfig::default());
+
+        let mut results = vec![];
+        for protocol_id in 0..2 {
+            let (tx, rx) = oneshot::channel();
+            runtime
+                .request_computation(room_id.clone(), 2, protocol_id, vec![], tx)
+                .await;
+            results.push(rx);
+        }
+
+        for (protocol_id, result) in results.into_iter().enumerate() {
+            let received = async_std::future::timeout(Duration::from_secs(20), result)
+                .await
+                .expect("session didn't complete")
+                .unwrap()
+                .unwrap();
+            assert_eq!(received, vec![protocol_id as u8]);
+        }
+    }
+
+    #[async_std::test]
+    async fn flooded_party_completes_protocol() {
+        let num_messages = 12;
+        let local = spawn_node("flood", vec![]).await;
+        let remote = spawn_node("flood", vec![local.address.clone()]).await;
+        let stranger = spawn_node("flood", vec![local.address.clone()]).await;
+        let room_id = local.room_id.clone();
+        let local_peer_id = local.address.peer_id;
+        for node in [&remote, &stranger] {
+            wait_connected(&node.service, local_peer_id).await;
+        }
+
+        // Remote party sends its messages at once, above the rate limit of the local party.
+        let flooding = TestFactory(Arc::new(move |protocol_id| TestAgent {
+            session_id: 0,
+            protocol_id,
+            compute: Box::new(move |parties, _incoming, outgoing| {
+                async move {
+                    let local = parties.index_of(parties.remotes().next().unwrap()).unwrap();
+                    let mut sent = vec![];
+                    for _ in 0..num_messages {
+                        let (sent_tx, sent_rx) = oneshot::channel();
+                        outgoing
+                            .send(OutgoingMessage {
+                                session_id: 0,
+                                body: vec![],
+                                to: MessageRouting::PointToPoint(local),
+                                sent: Some(sent_tx),
+                            })
+                            .await?;
+                        sent.push(sent_rx);
+                    }
+                    for sent_rx in sent {
+                        sent_rx.await?;
+                    }
+                    Ok(vec![])
+                }
+                .boxed()
+            }),
+        }));
+        let receiving = TestFactory(Arc::new(move |protocol_id| TestAgent {
+            session_id: 0,
+            protocol_id,
+            compute: Box::new(move |_parties, incoming, _outgoing| {
+                async move {
+                    let mut received = 0;
+                    while received < num_messages {
+                        if let IncomingEvent::Message(_) = incoming.recv().await? {
+                            received += 1;
+                        }
+                    }
+                    Ok(vec![received as u8])
+                }
+                .boxed()
+            }),
+        }));
+        let mut runtime = spawn_runtime(
+            local,
+            receiving,
+            RuntimeConfig {
+                incoming_rate_limit: Some(RateLimit {
+                    messages_per_sec: 50,
+                    burst: 4,
+                }),
+                ..Default::default()
+            },
+        );
+        let _remote_runtime = spawn_runtime(
+            remote,
+            flooding,
+            RuntimeConfig {
+                max_retransmissions: 10,
+                retransmission_delay: Duration::from_millis(200),
+                ..Default::default()
+            },
+        );
+
+        let (tx, rx) = oneshot::channel();
+        runtime
+            .request_computation(room_id.clone(), 2, 0, vec![], tx)
+            .await;
+
+        // Peer outside of the session floods it as well.
+        let (res_tx, _res_rx) = mpsc::channel(num_messages);
+        for seq in 0..num_messages {
+            stranger
+                .service
+                .send_message(
+                    &stranger.room_id,
+                    local_peer_id,
+                    computation_context(0, seq as u64),
+                    vec![],
+                    res_tx.clone(),
+                )
+                .await;
+        }
+
+        let result = async_std::future::timeout(Duration::from_secs(20), rx)
+            .await
+            .expect("session didn't complete")
+            .unwrap()
+            .unwrap();
+        assert_eq!(result, vec![num_messages as u8]);
+    }
+
+    #[async_std::test]
+    async fn mismatching_versions_never_computed() {
+        let joining = spawn_node("versions", vec![]).await;
+        let proposer = spawn_node("versions", vec![joining.address.clone()]).await;
+        let room_id = proposer.room_id.clone();
+        wait_connected(&proposer.service, joining.address.peer_id).await;
+
+        let computed = Arc::new(AtomicBool::new(false));
+        let mut runtime = spawn_runtime(
+            proposer,
+            VersionedFactory(1, computed.clone()),
+            RuntimeConfig::default(),
+        );
+        let _joining_runtime = spawn_runtime(
+            joining,
+            VersionedFactory(2, computed.clone()),
+            RuntimeConfig::default(),
+        );
+
+        let (tx, rx) = oneshot::channel();
+        runtime.request_computation(room_id, 2, 0, vec![], tx).await;
+        let result = async_std::future::timeout(Duration::from_secs(20), rx)
+            .await
+            .expect("session wasn't refused")
+            .unwrap();
+        assert!(result.is_err());
+        assert!(!computed.load(Ordering::SeqCst));
+    }
+
+    #[async_std::test]
+    async fn unauthenticated_party_disconnected() {
+        let joining = spawn_node("auth", vec![]).await;
+        let proposer = spawn_node("auth", vec![joining.address.clone()]).await;
+        let rejected = spawn_node("auth", vec![joining.address.clone()]).await;
+        let joining_peer_id = joining.address.peer_id;
+        let proposer_peer_id = proposer.service.local_peer_id();
+        let rejected_peer_id = rejected.service.local_peer_id();
+        for node in [&proposer, &rejected] {
+            wait_connected(&node.service, joining_peer_id).await;
+        }
+        let mut rejected_events = rejected.service.event_stream();
+
+        let (started_tx, mut started_rx) = mpsc::unbounded();
+        let factory = TestFactory(Arc::new(move |protocol_id| {
+            let started_tx = started_tx.clone();
+            TestAgent {
+                session_id: 0,
+                protocol_id,
+                compute: Box::new(move |_parties, _incoming, _outgoing| {
+                    async move {
+                        let _ = started_tx.unbounded_send(());
+                        Ok(vec![])
+                    }
+                    .boxed()
+                }),
+            }
+        }));
+        let config = RuntimeConfig {
+            authenticator: Arc::new(
+                AllowList::new()
+                    .allow(joining.room_id.clone(), [proposer_peer_id, joining_peer_id]),
+            ),
+            ..Default::default()
+        };
+        let _runtime = spawn_runtime(joining, factory, config);
+
+        // Only the proposer is allowed to propose, but it assembles the session with the rejected peer.
+        let context = MessageContext {
+            message_type: MessageType::Coordination,
+            session_id: 0,
+            protocol_id: 0,
+            seq: 0,
+        };
+        let (res_tx, mut res_rx) = mpsc::channel(2);
+        proposer
+            .service
+            .send_message(
+                &proposer.room_id,
+                joining_peer_id,
+                context,
+                encode_version(1),
+                res_tx.clone(),
+            )
+            .await;
+        assert!(res_rx.next().await.unwrap().is_ok());
+
+        let (parties, _) = Peerset::new(
+            vec![proposer_peer_id, joining_peer_id, rejected_peer_id].into_iter(),
+            proposer_peer_id,
+        );
+        let start_msg = StartMsg {
+            parties,
+            body: vec![],
+        };
+        proposer
+            .service
+            .send_message(
+                &proposer.room_id,
+                joining_peer_id,
+                context,
+                start_msg.to_bytes().unwrap(),
+                res_tx,
+            )
+            .await;
+
+        let disconnected = async {
+            while let Some(event) = rejected_events.next().await {
+                if let NetworkEvent::PeerDisconnected(peer_id) = event {
+                    if peer_id == joining_peer_id {
+                        return;
+                    }
+                }
+            }
+            panic!("network worker has stopped");
+        };
+        async_std::future::timeout(Duration::from_secs(10), disconnected)
+            .await
+            .expect("rejected party wasn't disconnected");
+        assert!(res_rx.next().await.unwrap().is_err());
+        assert!(started_rx.try_next().is_err());
+    }
+
+    #[async_std::test]
+    async fn authenticated_parties_admitted() {
+        let joining = spawn_node("admit", vec![]).await;
+        let proposer = spawn_node("admit", vec![joining.address.clone()]).await;
+        let room_id = proposer.room_id.clone();
+        let parties = [proposer.service.local_peer_id(), joining.address.peer_id];
+        wait_connected(&proposer.service, joining.address.peer_id).await;
+
+        let factory = || {
+            TestFactory(Arc::new(|protocol_id| TestAgent {
+                session_id: 0,
+                protocol_id,
+                compute: Box::new(|parties, _incoming, _outgoing| {
+                    async move { Ok(vec![parties.size() as u8]) }.boxed()
+                }),
+            }))
+        };
+        let config = || RuntimeConfig {
+            authenticator: Arc::new(AllowList::new().allow(room_id.clone(), parties)),
+            ..Default::default()
+        };
+        let mut runtime = spawn_runtime(proposer, factory(), config());
+        let _joining_runtime = spawn_runtime(joining, factory(), config());
+
+        let (tx, rx) = oneshot::channel();
+        runtime
+            .request_computation(room_id.clone(), 2, 0, vec![], tx)
+            .await;
+        let result = async_std::future::timeout(Duration::from_secs(20), rx)
+            .await
+            .expect("session didn't complete")
+            .unwrap()
+            .unwrap();
+        assert_eq!(result, vec![2]);
+    }
 }
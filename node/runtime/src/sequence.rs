@@ -0,0 +1,239 @@
+use crate::{MessageRouting, PartyIndex};
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+/// Assigns sequence numbers to the messages sent by the local party within a session.
+///
+/// Broadcasts reach every party, while point-to-point messages are numbered per receiver,
+/// so that no receiver observes gaps in any of the streams it is sent.
+#[derive(Default)]
+pub(crate) struct OutgoingSequence {
+    broadcast: u64,
+    direct: HashMap<PartyIndex, u64>,
+}
+
+impl OutgoingSequence {
+    /// Returns sequence number for the next message with the given routing.
+    pub fn next(&mut self, routing: MessageRouting) -> u64 {
+        let counter = match routing {
+            MessageRouting::Broadcast => &mut self.broadcast,
+            MessageRouting::PointToPoint(remote_index) => {
+                self.direct.entry(remote_index).or_default()
+            }
+        };
+
+        let seq = *counter;
+        *counter += 1;
+        seq
+    }
+}
+
+/// Outcome of pushing a message into the [`SequenceBuffer`].
+pub(crate) enum Sequenced<T> {
+    /// Messages that became deliverable, in sequence order. Empty if the message was buffered.
+    Ready(Vec<T>),
+    /// Message has already been delivered or buffered, handed back to the caller.
+    Duplicate(T),
+}
+
+/// Drops duplicate and stale messages, and reorders the ones arriving out of order.
+pub(crate) struct SequenceBuffer<T> {
+    streams: HashMap<(PartyIndex, bool), SequenceStream<T>>,
+    /// Maximum number of out-of-order messages buffered per stream before the gap is skipped.
+    window: usize,
+    /// Maximum time a gap in the stream is waited on before it is skipped.
+    gap_timeout: Duration,
+}
+
+struct SequenceStream<T> {
+    /// Sequence number expected to be delivered next.
+    next: u64,
+    /// Messages received ahead of `next`.
+    buffered: BTreeMap<u64, T>,
+    /// Time since the stream has been waiting on the missing `next` message.
+    gap_since: Option<Instant>,
+}
+
+impl<T> SequenceStream<T> {
+    /// Takes the messages that can be delivered starting from `next`.
+    fn take_ready(&mut self, now: Instant) -> Vec<T> {
+        let mut ready = vec![];
+        while let Some(item) = self.buffered.remove(&self.next) {
+            ready.push(item);
+            self.next += 1;
+        }
+
+        if self.buffered.is_empty() {
+            self.gap_since = None;
+        } else if self.gap_since.is_none() || !ready.is_empty() {
+            self.gap_since = Some(now);
+        }
+
+        ready
+    }
+
+    /// Skips ahead to the first of the buffered messages.
+    fn skip_gap(&mut self) {
+        if let Some(first) = self.buffered.keys().next() {
+            self.next = *first;
+        }
+    }
+}
+
+impl<T> SequenceBuffer<T> {
+    pub fn new(window: usize, gap_timeout: Duration) -> Self {
+        Self {
+            streams: HashMap::new(),
+            window,
+            gap_timeout,
+        }
+    }
+
+    /// Pushes a message from the stream of party `sender`, identified by the `is_broadcast` flag.
+    pub fn push(
+        &mut self,
+        sender: PartyIndex,
+        is_broadcast: bool,
+        seq: u64,
+        item: T,
+    ) -> Sequenced<T> {
+        let stream = self
+            .streams
+            .entry((sender, is_broadcast))
+            .or_insert_with(|| SequenceStream {
+                next: 0,
+                buffered: BTreeMap::new(),
+                gap_since: None,
+            });
+
+        if seq < stream.next || stream.buffered.contains_key(&seq) {
+            return Sequenced::Duplicate(item);
+        }
+
+        stream.buffered.insert(seq, item);
+
+        // The missing message is unlikely to ever arrive, skip ahead to the buffered ones.
+        if stream.buffered.len() > self.window {
+            stream.skip_gap();
+        }
+
+        Sequenced::Ready(stream.take_ready(Instant::now()))
+    }
+
+    /// Skips the gaps that have been waited on for longer than the gap timeout by `now`,
+    /// returning the messages that became deliverable.
+    pub fn flush_expired(&mut self, now: Instant) -> Vec<T> {
+        let mut ready = vec![];
+        for stream in self.streams.values_mut() {
+            if matches!(stream.gap_since, Some(since) if now.duration_since(since) >= self.gap_timeout)
+            {
+                stream.skip_gap();
+                ready.extend(stream.take_ready(now));
+            }
+        }
+
+        ready
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sequence::{OutgoingSequence, SequenceBuffer, Sequenced};
+    use crate::{MessageRouting, PartyIndex};
+    use std::time::{Duration, Instant};
+
+    const GAP_TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn sequence_buffer<T>() -> SequenceBuffer<T> {
+        SequenceBuffer::new(16, GAP_TIMEOUT)
+    }
+
+    fn party(i: u16) -> PartyIndex {
+        PartyIndex::from(i)
+    }
+
+    #[test]
+    fn duplicate_delivery() {
+        let mut buffer = sequence_buffer();
+
+        assert!(
+            matches!(buffer.push(party(1), false, 0, "a"), Sequenced::Ready(r) if r == vec!["a"])
+        );
+        assert!(matches!(
+            buffer.push(party(1), false, 0, "a"),
+            Sequenced::Duplicate("a")
+        ));
+
+        // Same sequence number in the other stream isn't a duplicate.
+        assert!(
+            matches!(buffer.push(party(1), true, 0, "b"), Sequenced::Ready(r) if r == vec!["b"])
+        );
+    }
+
+    #[test]
+    fn out_of_order_pair() {
+        let mut buffer = sequence_buffer();
+
+        assert!(
+            matches!(buffer.push(party(2), true, 1, "second"), Sequenced::Ready(r) if r.is_empty())
+        );
+        assert!(
+            matches!(buffer.push(party(2), true, 0, "first"), Sequenced::Ready(r) if r == vec!["first", "second"])
+        );
+        assert!(matches!(
+            buffer.push(party(2), true, 1, "second"),
+            Sequenced::Duplicate(_)
+        ));
+    }
+
+    #[test]
+    fn gap_skipped_after_timeout() {
+        let mut buffer = sequence_buffer();
+
+        assert!(
+            matches!(buffer.push(party(1), false, 0, "first"), Sequenced::Ready(r) if r == vec!["first"])
+        );
+        assert!(
+            matches!(buffer.push(party(1), false, 2, "third"), Sequenced::Ready(r) if r.is_empty())
+        );
+        assert!(buffer.flush_expired(Instant::now()).is_empty());
+
+        let ready = buffer.flush_expired(Instant::now() + GAP_TIMEOUT);
+        assert_eq!(ready, vec!["third"]);
+
+        // Missing message arriving late is stale.
+        assert!(matches!(
+            buffer.push(party(1), false, 1, "second"),
+            Sequenced::Duplicate(_)
+        ));
+        assert!(buffer
+            .flush_expired(Instant::now() + GAP_TIMEOUT)
+            .is_empty());
+    }
+
+    #[test]
+    fn gap_skipped_beyond_window() {
+        let mut buffer = SequenceBuffer::new(2, GAP_TIMEOUT);
+
+        assert!(
+            matches!(buffer.push(party(1), false, 1, "second"), Sequenced::Ready(r) if r.is_empty())
+        );
+        assert!(
+            matches!(buffer.push(party(1), false, 2, "third"), Sequenced::Ready(r) if r.is_empty())
+        );
+        assert!(
+            matches!(buffer.push(party(1), false, 3, "fourth"), Sequenced::Ready(r) if r == vec!["second", "third", "fourth"])
+        );
+    }
+
+    #[test]
+    fn outgoing_streams_have_no_gaps() {
+        let mut seq = OutgoingSequence::default();
+
+        assert_eq!(seq.next(MessageRouting::Broadcast), 0);
+        assert_eq!(seq.next(MessageRouting::PointToPoint(2.into())), 0);
+        assert_eq!(seq.next(MessageRouting::PointToPoint(3.into())), 0);
+        assert_eq!(seq.next(MessageRouting::PointToPoint(2.into())), 1);
+        assert_eq!(seq.next(MessageRouting::Broadcast), 1);
+    }
+}
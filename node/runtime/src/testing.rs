@@ -0,0 +1,230 @@
+use crate::echo::EchoGadget;
+use crate::execution::ProtocolExecution;
+use crate::peerset::Peerset;
+use crate::retransmit::PendingMessage;
+use crate::{
+    ComputeAgentAsync, IncomingEvent, OutgoingMessage, PersistentCacher, ProtocolAgentFactory,
+    RuntimeConfig, RuntimeDaemon, RuntimeService,
+};
+use futures::channel::{mpsc, oneshot};
+use futures::future::BoxFuture;
+use libp2p::identity::Keypair;
+use libp2p::PeerId;
+use mpc_p2p::{
+    broadcast, MessageContext, MessageType, MultiaddrWithPeerId, NetworkService, NetworkWorker,
+    NodeKeyConfig, Params, RoomArgs, RoomId,
+};
+use std::iter;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Generates the ids of `n` distinct peers with ed25519 identities, as nodes have.
+pub(crate) fn peer_ids(n: usize) -> Vec<PeerId> {
+    iter::repeat_with(|| Keypair::generate_ed25519().public().to_peer_id())
+        .take(n)
+        .collect()
+}
+
+/// Context of the computation message numbered `seq` in the session with `session_id`.
+pub(crate) fn computation_context(session_id: u64, seq: u64) -> MessageContext {
+    MessageContext {
+        message_type: MessageType::Computation,
+        session_id,
+        protocol_id: 0,
+        seq,
+    }
+}
+
+/// First computation message of the session to the `peer_id`, not resent yet.
+pub(crate) fn pending_message(
+    peer_id: PeerId,
+    body: Vec<u8>,
+    sent: oneshot::Sender<()>,
+) -> PendingMessage {
+    PendingMessage {
+        peer_id,
+        context: computation_context(0, 0),
+        body,
+        sent: Some(sent),
+        retries: 0,
+    }
+}
+
+/// Network node running on the loopback interface.
+pub(crate) struct TestNode {
+    pub service: NetworkService,
+    pub address: MultiaddrWithPeerId,
+    pub room_id: RoomId,
+    pub room_rx: mpsc::Receiver<broadcast::IncomingMessage>,
+}
+
+/// Spawns a node joined to the `room` with the given `boot_peers`, listening on a random port.
+pub(crate) async fn spawn_node(room: &str, boot_peers: Vec<MultiaddrWithPeerId>) -> TestNode {
+    spawn_node_with_key(room, boot_peers, NodeKeyConfig::default()).await
+}
+
+/// Spawns a node as [`spawn_node`] does, with the identity of the given `node_key`.
+pub(crate) async fn spawn_node_with_key(
+    room: &str,
+    boot_peers: Vec<MultiaddrWithPeerId>,
+    node_key: NodeKeyConfig,
+) -> TestNode {
+    let (room_id, room_args, room_rx) =
+        RoomArgs::new_full(room.to_string(), boot_peers.into_iter(), 16);
+    let params = Params {
+        listen_address: "/ip4/127.0.0.1/tcp/0".parse().unwrap(),
+        mdns: false,
+        // Keeps the boot peers among the room peers before they are connected.
+        kademlia: true,
+        kademlia_bootstrap_max_retries: 0,
+        kademlia_bootstrap_base_delay: Duration::from_secs(1),
+        kademlia_bootstrap_max_delay: Duration::from_secs(60),
+        boot_peers_timeout: Duration::from_secs(30),
+        kademlia_store: None,
+        kad_protocol_name: None,
+        dial_failure_threshold: 0,
+        dial_failure_window: Duration::from_secs(60),
+        dial_failure_cooldown: Duration::from_secs(300),
+        rooms: vec![room_args],
+    };
+
+    let (worker, service) = NetworkWorker::new(node_key, params).unwrap();
+    async_std::task::spawn(worker.run());
+
+    let multiaddr = loop {
+        if let Some(address) = service.listen_addresses().await.into_iter().next() {
+            break address;
+        }
+        async_std::task::sleep(Duration::from_millis(10)).await;
+    };
+
+    TestNode {
+        address: MultiaddrWithPeerId {
+            multiaddr,
+            peer_id: service.local_peer_id(),
+        },
+        service,
+        room_id,
+        room_rx,
+    }
+}
+
+/// Waits until the node is connected to the peer, e.g. to a boot peer it dials on start.
+pub(crate) async fn wait_connected(service: &NetworkService, peer_id: PeerId) {
+    let connected = async {
+        while !service.connected_peers().await.contains(&peer_id) {
+            async_std::task::sleep(Duration::from_millis(10)).await;
+        }
+    };
+    async_std::future::timeout(Duration::from_secs(10), connected)
+        .await
+        .expect("peer wasn't connected");
+}
+
+/// Computation run by the [`TestAgent`].
+pub(crate) type TestCompute = Box<
+    dyn FnOnce(
+            Peerset,
+            async_channel::Receiver<IncomingEvent>,
+            async_channel::Sender<OutgoingMessage>,
+        ) -> BoxFuture<'static, anyhow::Result<Vec<u8>>>
+        + Send
+        + Sync,
+>;
+
+/// Agent running the given computation of `protocol_id` in the session with `session_id`.
+pub(crate) struct TestAgent {
+    pub session_id: u64,
+    pub protocol_id: u64,
+    pub compute: TestCompute,
+}
+
+#[async_trait::async_trait]
+impl ComputeAgentAsync for TestAgent {
+    fn session_id(&self) -> u64 {
+        self.session_id
+    }
+
+    fn protocol_id(&self) -> u64 {
+        self.protocol_id
+    }
+
+    fn protocol_version(&self) -> u16 {
+        1
+    }
+
+    async fn compute(
+        self: Box<Self>,
+        parties: Peerset,
+        _args: Vec<u8>,
+        incoming: async_channel::Receiver<IncomingEvent>,
+        outgoing: async_channel::Sender<OutgoingMessage>,
+    ) -> anyhow::Result<Vec<u8>> {
+        (self.compute)(parties, incoming, outgoing).await
+    }
+}
+
+/// Factory making the agent of each protocol with the given closure.
+pub(crate) struct TestFactory(pub Arc<dyn Fn(u64) -> TestAgent + Send + Sync>);
+
+impl ProtocolAgentFactory for TestFactory {
+    fn make(&self, protocol_id: u64) -> crate::Result<Box<dyn ComputeAgentAsync>> {
+        Ok(Box::new((self.0)(protocol_id)))
+    }
+}
+
+/// Spawns the execution of the `agent` on the `node` as one of the `parties`, returning the
+/// receiver of the computation result.
+pub(crate) fn execute(
+    node: TestNode,
+    parties: Vec<PeerId>,
+    agent: TestAgent,
+    config: &RuntimeConfig,
+) -> oneshot::Receiver<anyhow::Result<Vec<u8>>> {
+    let local_peer_id = node.service.local_peer_id();
+    let (parties, peerset_rx) = Peerset::new(parties.into_iter(), local_peer_id);
+    let (echo, echo_tx) = EchoGadget::new(parties.size());
+    let (tx, rx) = oneshot::channel();
+
+    let execution = ProtocolExecution::new(
+        node.room_id,
+        vec![],
+        Box::new(agent),
+        node.service,
+        parties,
+        peerset_rx,
+        PersistentCacher::new(std::env::temp_dir(), local_peer_id),
+        node.room_rx,
+        echo_tx,
+        config,
+        Some(tx),
+    );
+    async_std::task::spawn(echo.wrap_execution(execution));
+
+    rx
+}
+
+/// Spawns the runtime of the `node`, making the agents with the `factory`.
+pub(crate) fn spawn_runtime<TFactory>(
+    node: TestNode,
+    factory: TFactory,
+    config: RuntimeConfig,
+) -> RuntimeService
+where
+    TFactory: ProtocolAgentFactory + Send + Sync + Unpin + 'static,
+{
+    let local_peer_id = node.service.local_peer_id();
+    let (runtime, service) = match RuntimeDaemon::new(
+        node.service,
+        iter::once((node.room_id, node.room_rx)),
+        factory,
+        PersistentCacher::new(std::env::temp_dir(), local_peer_id),
+        config,
+    ) {
+        Ok(res) => res,
+        Err(e) => panic!("{e}"),
+    };
+    async_std::task::spawn(runtime.run());
+
+    service
+}
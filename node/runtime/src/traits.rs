@@ -1,35 +1,79 @@
-use crate::peerset::Peerset;
+use crate::peerset::{PartyIndex, Peerset};
 
 use futures::channel::oneshot;
 use mpc_p2p::RoomId;
 
 pub struct IncomingMessage {
+    /// Session the message belongs to.
+    pub session_id: u64,
+
     /// Index of party who sent the message.
-    pub from: u16,
+    pub from: PartyIndex,
 
     /// Message sent by the remote.
     pub body: Vec<u8>,
 
     pub to: MessageRouting,
+
+    /// Sequence number of the message in the sender's stream, duplicates are never delivered.
+    pub seq: u64,
+}
+
+/// Event delivered to the compute agent during the session.
+pub enum IncomingEvent {
+    /// Message received from the remote party.
+    Message(IncomingMessage),
+
+    /// Party with the given index has disconnected in the middle of the session.
+    PartyLeft(PartyIndex),
 }
 
 pub struct OutgoingMessage {
+    /// Session the message belongs to, must match the [`ComputeAgentAsync::session_id`].
+    pub session_id: u64,
+
     /// Message sent by the remote.
     pub body: Vec<u8>,
 
     pub to: MessageRouting,
 
+    /// Notified once the message is delivered.
+    ///
+    /// Point-to-point messages are acknowledged once the receiver has them, being resent after
+    /// reconnects if needed, while broadcasts only once every remote party in the [`Peerset`]
+    /// has received the message. If delivery to any of the parties fails the sender is dropped,
+    /// so the awaiting side observes cancellation instead.
     pub sent: Option<oneshot::Sender<()>>,
 }
 
 #[derive(Copy, Clone, Debug)]
 pub enum MessageRouting {
     Broadcast,
-    PointToPoint(u16),
+    PointToPoint(PartyIndex),
 }
 
 pub trait ProtocolAgentFactory {
     fn make(&self, protocol_id: u64) -> crate::Result<Box<dyn ComputeAgentAsync>>;
+
+    /// Makes an agent for the protocol `version` requested by the remote party.
+    /// Fails with [`Error::UnsupportedVersion`](crate::Error::UnsupportedVersion) if the local
+    /// implementation runs another version.
+    fn make_versioned(
+        &self,
+        protocol_id: u64,
+        version: u16,
+    ) -> crate::Result<Box<dyn ComputeAgentAsync>> {
+        let agent = self.make(protocol_id)?;
+        if agent.protocol_version() != version {
+            return Err(crate::Error::UnsupportedVersion {
+                protocol_id,
+                requested: version,
+                supported: agent.protocol_version(),
+            });
+        }
+
+        Ok(agent)
+    }
 }
 
 #[async_trait::async_trait]
@@ -38,11 +82,21 @@ pub trait ComputeAgentAsync: Send + Sync {
 
     fn protocol_id(&self) -> u64;
 
+    /// Version of the protocol implementation, all parties in the session must run the same one.
+    fn protocol_version(&self) -> u16;
+
+    /// Runs the protocol, exchanging messages with the other parties through `incoming` and
+    /// `outgoing` channels.
+    ///
+    /// Sending into `outgoing` waits once the runtime has as many messages queued and in flight
+    /// as the configured capacity, until the transport flushes some of them. Agents shouldn't
+    /// hold on receiving from `incoming` while waiting, e.g. when broadcasting to many slow
+    /// parties, to not stall the other parties of the session.
     async fn compute(
         self: Box<Self>,
         parties: Peerset,
         args: Vec<u8>,
-        incoming: async_channel::Receiver<IncomingMessage>,
+        incoming: async_channel::Receiver<IncomingEvent>,
         outgoing: async_channel::Sender<OutgoingMessage>,
     ) -> anyhow::Result<Vec<u8>>;
 }
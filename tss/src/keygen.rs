@@ -5,7 +5,7 @@ use futures::future::TryFutureExt;
 use futures::StreamExt;
 use futures_util::{pin_mut, FutureExt};
 use log::info;
-use mpc_runtime::{IncomingMessage, OutgoingMessage, Peerset};
+use mpc_runtime::{ComputeAgentAsync, IncomingEvent, OutgoingMessage, Peerset};
 use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2020::state_machine::keygen::{
     Keygen, LocalKey,
 };
@@ -29,22 +29,27 @@ impl mpc_runtime::ComputeAgentAsync for KeyGen {
         0
     }
 
+    fn protocol_version(&self) -> u16 {
+        1
+    }
+
     async fn compute(
         mut self: Box<Self>,
         mut parties: Peerset,
         args: Vec<u8>,
-        incoming: async_channel::Receiver<IncomingMessage>,
+        incoming: async_channel::Receiver<IncomingEvent>,
         outgoing: async_channel::Sender<OutgoingMessage>,
     ) -> anyhow::Result<Vec<u8>> {
         let n = parties.len() as u16;
-        let i = parties.index_of(parties.local_peer_id()).unwrap() + 1;
+        let i = u16::from(parties.index_of(parties.local_peer_id()).unwrap()) + 1;
         let mut io = BufReader::new(&*args);
         let t = unsigned_varint::io::read_u16(&mut io).unwrap();
 
         let state_machine =
             Keygen::new(i, t, n).map_err(|e| anyhow!("failed building state {e}"))?;
 
-        let (incoming, outgoing) = crate::round_based::state_replication(incoming, outgoing);
+        let (incoming, outgoing) =
+            crate::round_based::state_replication(self.session_id(), incoming, outgoing);
 
         let incoming = incoming.fuse();
         pin_mut!(incoming, outgoing);
@@ -17,7 +17,7 @@ use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2020::state_machine::sig
 };
 use round_based::{AsyncProtocol, Msg};
 
-use mpc_runtime::{IncomingMessage, OutgoingMessage, Peerset};
+use mpc_runtime::{ComputeAgentAsync, IncomingEvent, OutgoingMessage, Peerset};
 
 pub struct KeySign {
     path: String,
@@ -26,35 +26,43 @@ pub struct KeySign {
 #[async_trait::async_trait]
 impl mpc_runtime::ComputeAgentAsync for KeySign {
     fn session_id(&self) -> u64 {
-        0
+        // Distinct from the one of `KeyGen`, so that both can run in the same room at once.
+        1
     }
 
     fn protocol_id(&self) -> u64 {
         1
     }
 
+    fn protocol_version(&self) -> u16 {
+        1
+    }
+
     async fn compute(
         mut self: Box<Self>,
         mut parties: Peerset,
         args: Vec<u8>,
-        rt_incoming: async_channel::Receiver<IncomingMessage>,
+        rt_incoming: async_channel::Receiver<IncomingEvent>,
         rt_outgoing: async_channel::Sender<OutgoingMessage>,
     ) -> anyhow::Result<Vec<u8>> {
         parties.recover_from_cache().await?;
-        let i = parties.index_of(parties.local_peer_id()).unwrap() + 1;
+        let i = u16::from(parties.index_of(parties.local_peer_id()).unwrap()) + 1;
         let n = parties.len();
         let s_l = parties
-            .parties_indexes
+            .parties_indexes()
             .iter()
-            .map(|i| (*i + 1) as u16)
+            .map(|i| u16::from(*i) + 1)
             .collect();
         let local_key = self.read_local_key()?;
 
         let state_machine = OfflineStage::new(i, s_l, local_key)
             .map_err(|e| anyhow!("failed building state {e}"))?;
 
-        let (incoming, outgoing) =
-            crate::round_based::state_replication(rt_incoming.clone(), rt_outgoing.clone());
+        let (incoming, outgoing) = crate::round_based::state_replication(
+            self.session_id(),
+            rt_incoming.clone(),
+            rt_outgoing.clone(),
+        );
 
         let incoming = incoming.fuse();
         pin_mut!(incoming, outgoing);
@@ -64,7 +72,8 @@ impl mpc_runtime::ComputeAgentAsync for KeySign {
             .await
             .map_err(|e| anyhow!("protocol execution terminated with error: {e}"))?;
 
-        let (incoming, outgoing) = crate::round_based::state_replication(rt_incoming, rt_outgoing);
+        let (incoming, outgoing) =
+            crate::round_based::state_replication(self.session_id(), rt_incoming, rt_outgoing);
         pin_mut!(incoming, outgoing);
 
         let (signing, partial_signature) =
@@ -3,14 +3,15 @@ use futures::channel::oneshot;
 use futures::{Sink, Stream};
 use futures_util::{SinkExt, StreamExt};
 use log::info;
-use mpc_runtime::{IncomingMessage, MessageRouting, OutgoingMessage};
+use mpc_runtime::{IncomingEvent, MessageRouting, OutgoingMessage, PartyIndex};
 use round_based::Msg;
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 use std::fmt::Debug;
 
 pub(crate) fn state_replication<M>(
-    incoming: async_channel::Receiver<IncomingMessage>,
+    session_id: u64,
+    incoming: async_channel::Receiver<IncomingEvent>,
     outgoing: async_channel::Sender<OutgoingMessage>,
 ) -> (
     impl Stream<Item = Result<Msg<M>, anyhow::Error>>,
@@ -19,14 +20,20 @@ pub(crate) fn state_replication<M>(
 where
     M: Serialize + DeserializeOwned + Debug,
 {
-    let incoming = incoming.map(move |msg: IncomingMessage| {
+    let incoming = incoming.map(move |event: IncomingEvent| {
+        let msg = match event {
+            IncomingEvent::Message(msg) => msg,
+            IncomingEvent::PartyLeft(index) => {
+                return Err(anyhow!("party {index} has left the session"));
+            }
+        };
         let body: M = serde_ipld_dagcbor::from_slice(&*msg.body).unwrap();
 
         Ok(Msg::<M> {
-            sender: msg.from,
+            sender: u16::from(msg.from) + 1,
             receiver: match msg.to {
                 MessageRouting::Broadcast => None,
-                MessageRouting::PointToPoint(i) => Some(i),
+                MessageRouting::PointToPoint(i) => Some(u16::from(i) + 1),
             },
             body,
         })
@@ -37,9 +44,12 @@ where
         let (tx, rx) = oneshot::channel();
         outgoing
             .send(OutgoingMessage {
+                session_id,
                 body: payload,
                 to: match message.receiver {
-                    Some(remote_index) => MessageRouting::PointToPoint(remote_index),
+                    Some(remote_index) => {
+                        MessageRouting::PointToPoint(PartyIndex::from(remote_index - 1))
+                    }
                     None => MessageRouting::Broadcast,
                 },
                 sent: Some(tx),